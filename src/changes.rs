@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::matchers::Match;
+
+/// A match as it was last notified about.
+#[derive(Deserialize, Serialize)]
+pub struct Fingerprint {
+    pub hash: String,
+    #[serde(flatten)]
+    pub found: Match,
+}
+
+impl Fingerprint {
+    pub fn new(found: &Match) -> Fingerprint {
+        let mut hasher = Sha256::new();
+        hasher.update(found.content.as_bytes());
+        for (name, value) in &found.fields {
+            hasher.update([0]);
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update(value.as_bytes());
+        }
+
+        Fingerprint {
+            hash: format!("{:x}", hasher.finalize()),
+            found: found.clone(),
+        }
+    }
+}
+
+/// What's different between the last notified matches and this run's.
+pub struct Changes {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    /// Every added, removed or changed match with a `change` field saying which
+    pub matches: Vec<Match>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} changed",
+            self.added, self.removed, self.changed
+        )
+    }
+}
+
+/// Compares this run's matches with the previous ones, pairing them up by `Match::key`. Each
+/// previous match pairs with one current match at most, so a repeated match that shows up more
+/// often than before counts as added.
+pub fn diff_matches(previous: &[Fingerprint], current: &[Match]) -> Changes {
+    let mut changes = Changes {
+        added: 0,
+        removed: 0,
+        changed: 0,
+        matches: Vec::new(),
+    };
+
+    let fingerprints = current
+        .iter()
+        .map(Fingerprint::new)
+        .collect::<Vec<Fingerprint>>();
+    let mut is_paired = vec![false; previous.len()];
+    // pair identical matches first, so one that changed isn't taken for its unchanged duplicate
+    let unchanged = fingerprints
+        .iter()
+        .map(|fingerprint| {
+            take_pair(previous, &mut is_paired, |before| {
+                before.hash == fingerprint.hash && before.found.key() == fingerprint.found.key()
+            })
+        })
+        .collect::<Vec<Option<usize>>>();
+
+    for (fingerprint, unchanged) in fingerprints.iter().zip(unchanged) {
+        if unchanged.is_some() {
+            continue;
+        }
+
+        let found = &fingerprint.found;
+        match take_pair(previous, &mut is_paired, |before| {
+            before.found.key() == found.key()
+        }) {
+            Some(index) => {
+                changes.changed += 1;
+                changes
+                    .matches
+                    .push(changed_match(&previous[index].found, found));
+            }
+            None => {
+                changes.added += 1;
+                changes.matches.push(with_change("added", found.clone()));
+            }
+        }
+    }
+
+    for (before, is_paired) in previous.iter().zip(is_paired) {
+        if !is_paired {
+            changes.removed += 1;
+            changes
+                .matches
+                .push(with_change("removed", before.found.clone()));
+        }
+    }
+
+    changes
+}
+
+/// The first previous match that isn't paired yet and `is_pair` accepts, marked as paired.
+fn take_pair(
+    previous: &[Fingerprint],
+    is_paired: &mut [bool],
+    is_pair: impl Fn(&Fingerprint) -> bool,
+) -> Option<usize> {
+    let index =
+        (0..previous.len()).find(|index| !is_paired[*index] && is_pair(&previous[*index]))?;
+    is_paired[index] = true;
+    Some(index)
+}
+
+fn with_change(change: &str, mut found: Match) -> Match {
+    found
+        .fields
+        .insert(0, ("change".to_string(), change.to_string()));
+    found
+}
+
+/// The new match with each field that changed shown as `old -> new`.
+fn changed_match(before: &Match, after: &Match) -> Match {
+    let mut found = after.clone();
+    for (name, value) in found.fields.iter_mut() {
+        let old_value = before
+            .fields
+            .iter()
+            .find(|(old_name, _)| old_name == name)
+            .map(|(_, old_value)| old_value);
+        if let Some(old_value) = old_value.filter(|old_value| *old_value != value) {
+            *value = format!("{} -> {}", old_value, value);
+        }
+    }
+    with_change("changed", found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprints(matches: &[Match]) -> Vec<Fingerprint> {
+        matches.iter().map(Fingerprint::new).collect()
+    }
+
+    fn keyed(key: &str, price: &str) -> Match {
+        Match {
+            fields: vec![("price".to_string(), price.to_string())],
+            key: Some(key.to_string()),
+            ..Match::new(format!("{} {}", key, price))
+        }
+    }
+
+    fn changes_of(changes: &Changes) -> Vec<(&str, &str)> {
+        changes
+            .matches
+            .iter()
+            .map(|found| (found.fields[0].1.as_str(), found.key()))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_matches_are_not_changes() {
+        let matches = [Match::new("a".to_string()), Match::new("b".to_string())];
+        let changes = diff_matches(&fingerprints(&matches), &matches);
+        assert!(changes.is_empty());
+        assert_eq!(changes.summary(), "0 added, 0 removed, 0 changed");
+    }
+
+    #[test]
+    fn reports_added_and_removed_matches() {
+        let previous = fingerprints(&[Match::new("a".to_string()), Match::new("b".to_string())]);
+        let current = [Match::new("b".to_string()), Match::new("c".to_string())];
+        let changes = diff_matches(&previous, &current);
+        assert_eq!((changes.added, changes.removed, changes.changed), (1, 1, 0));
+        assert_eq!(changes_of(&changes), [("added", "c"), ("removed", "a")]);
+    }
+
+    #[test]
+    fn reports_changed_fields_of_keyed_matches() {
+        let previous = fingerprints(&[keyed("$.a", "10"), keyed("$.b", "20")]);
+        let current = [keyed("$.a", "12"), keyed("$.b", "20")];
+        let changes = diff_matches(&previous, &current);
+        assert_eq!((changes.added, changes.removed, changes.changed), (0, 0, 1));
+        assert_eq!(changes_of(&changes), [("changed", "$.a")]);
+        assert_eq!(changes.matches[0].fields[1].1, "10 -> 12");
+    }
+
+    #[test]
+    fn counts_duplicate_matches() {
+        let previous = fingerprints(&[Match::new("a".to_string())]);
+        let current = [
+            Match::new("a".to_string()),
+            Match::new("a".to_string()),
+            Match::new("a".to_string()),
+        ];
+        let changes = diff_matches(&previous, &current);
+        assert_eq!((changes.added, changes.removed, changes.changed), (2, 0, 0));
+
+        let changes = diff_matches(&fingerprints(&current), &current[..1]);
+        assert_eq!((changes.added, changes.removed, changes.changed), (0, 2, 0));
+    }
+
+    #[test]
+    fn pairs_duplicate_keys_with_identical_matches_first() {
+        let previous = fingerprints(&[keyed("$.a", "10"), keyed("$.a", "20")]);
+        let current = [keyed("$.a", "20"), keyed("$.a", "30")];
+        let changes = diff_matches(&previous, &current);
+        assert_eq!((changes.added, changes.removed, changes.changed), (0, 0, 1));
+        assert_eq!(changes.matches[0].fields[1].1, "10 -> 30");
+    }
+}
@@ -0,0 +1,1831 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use derive_builder::Builder;
+use lettre::message::Mailbox;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::Url;
+use scraper::Selector;
+use serde::Deserialize;
+
+use crate::daemon::parse_schedule;
+use crate::error::{GemError, GemResult};
+use crate::fetch::{content_hash, parse_method, template_urls};
+use crate::hosts::HostLimits;
+use crate::matchers::{compile_json_path, compile_pattern, compile_xpath, parse_filter};
+use crate::quiet_hours::QuietHours;
+use crate::rate_limit::RateLimit;
+use crate::state::{StoreKind, MAX_RUNS};
+
+pub const CONFIG_FILE_KEY: &str = "CONFIG_FILE";
+
+/// Appended to any key to read its value from a file instead, e.g. `SMTP_PASS_FILE`
+pub const FILE_SUFFIX: &str = "_FILE";
+
+pub const TARGET_URL_KEY: &str = "TARGET_URL";
+pub const SEARCH_TEXT_KEY: &str = "SEARCH_TEXT";
+pub const CONTENT_TYPE_KEY: &str = "CONTENT_TYPE";
+pub const SELECTOR_KEY: &str = "SELECTOR";
+pub const PATTERN_KEY: &str = "PATTERN";
+pub const PATTERN_FLAGS_KEY: &str = "PATTERN_FLAGS";
+pub const XPATH_KEY: &str = "XPATH";
+pub const JSON_PATH_KEY: &str = "JSON_PATH";
+pub const CSV_FILTER_KEY: &str = "CSV_FILTER";
+pub const MATCH_MODE_KEY: &str = "MATCH_MODE";
+pub const NOTIFY_ON_KEY: &str = "NOTIFY_ON";
+pub const REQUIRE_CONSECUTIVE_KEY: &str = "REQUIRE_CONSECUTIVE";
+pub const NOTIFY_RECOVERY_KEY: &str = "NOTIFY_RECOVERY";
+
+pub const REQUEST_METHOD_KEY: &str = "REQUEST_METHOD";
+/// `Name: value` pairs separated by `|`
+pub const REQUEST_HEADERS_KEY: &str = "REQUEST_HEADERS";
+pub const REQUEST_BODY_KEY: &str = "REQUEST_BODY";
+/// `name=value` pairs separated by `;`, as in a `Cookie` header
+pub const REQUEST_COOKIES_KEY: &str = "REQUEST_COOKIES";
+pub const USER_AGENT_KEY: &str = "USER_AGENT";
+pub const RESPECT_ROBOTS_KEY: &str = "RESPECT_ROBOTS";
+pub const SKIP_UNCHANGED_KEY: &str = "SKIP_UNCHANGED";
+pub const NEXT_PAGE_SELECTOR_KEY: &str = "NEXT_PAGE_SELECTOR";
+/// Comma separated, each can contain `{page}`
+pub const PAGE_URLS_KEY: &str = "PAGE_URLS";
+pub const MAX_PAGES_KEY: &str = "MAX_PAGES";
+pub const PROXY_KEY: &str = "PROXY";
+pub const CA_CERT_KEY: &str = "CA_CERT";
+pub const CLIENT_CERT_KEY: &str = "CLIENT_CERT";
+pub const CLIENT_KEY_KEY: &str = "CLIENT_KEY";
+/// `host=ip:port` pairs separated by `,`
+pub const RESOLVE_HOSTS_KEY: &str = "RESOLVE_HOSTS";
+pub const INSECURE_TLS_KEY: &str = "INSECURE_TLS";
+pub const CONNECT_TIMEOUT_S_KEY: &str = "CONNECT_TIMEOUT_S";
+pub const TIMEOUT_S_KEY: &str = "TIMEOUT_S";
+pub const RETRIES_KEY: &str = "RETRIES";
+pub const RETRY_DELAY_S_KEY: &str = "RETRY_DELAY_S";
+/// Comma separated status codes
+pub const RETRY_STATUSES_KEY: &str = "RETRY_STATUSES";
+pub const LOGIN_URL_KEY: &str = "LOGIN_URL";
+pub const LOGIN_SUBMIT_URL_KEY: &str = "LOGIN_SUBMIT_URL";
+pub const LOGIN_CSRF_SELECTOR_KEY: &str = "LOGIN_CSRF_SELECTOR";
+pub const LOGIN_CSRF_FIELD_KEY: &str = "LOGIN_CSRF_FIELD";
+pub const LOGIN_USERNAME_FIELD_KEY: &str = "LOGIN_USERNAME_FIELD";
+pub const LOGIN_PASSWORD_FIELD_KEY: &str = "LOGIN_PASSWORD_FIELD";
+pub const LOGIN_USERNAME_KEY: &str = "LOGIN_USERNAME";
+pub const LOGIN_PASSWORD_KEY: &str = "LOGIN_PASSWORD";
+
+pub const SCHEDULE_KEY: &str = "SCHEDULE";
+pub const CHECK_INTERVAL_S_KEY: &str = "CHECK_INTERVAL_S";
+pub const JITTER_S_KEY: &str = "JITTER_S";
+
+pub const NOTIFICATION_TYPE_KEY: &str = "NOTIFICATION_TYPE";
+pub const NOTIFICATION_MAX_PER_INTERVAL_KEY: &str = "NOTIFICATION_MAX_PER_INTERVAL";
+pub const NOTIFICATION_INTERVAL_S_KEY: &str = "NOTIFICATION_INTERVAL_S";
+pub const NOTIFICATION_WRITE_DIR_KEY: &str = "NOTIFICATION_WRITE_DIR";
+const DEFAULT_NOTIFICATION_WRITE_DIR: &str = "./";
+pub const STATE_STORE_KEY: &str = "STATE_STORE";
+pub const STATE_DB_KEY: &str = "STATE_DB";
+/// Seconds between requests to the same host, across every target
+pub const HOST_DELAY_S_KEY: &str = "HOST_DELAY_S";
+/// Requests to the same host in flight at once, across every target
+pub const HOST_CONCURRENCY_KEY: &str = "HOST_CONCURRENCY";
+
+pub const DEBUG_KEY: &str = "DEBUG";
+pub const PREVENT_EMAIL_KEY: &str = "PREVENT_EMAIL";
+pub const PREVENT_MESSAGE_KEY: &str = "PREVENT_MESSAGE";
+
+pub const SMTP_USER_KEY: &str = "SMTP_USER";
+pub const SMTP_PASS_KEY: &str = "SMTP_PASS";
+pub const SMTP_RELAY_KEY: &str = "SMTP_RELAY";
+
+pub const EMAIL_TO_KEY: &str = "EMAIL_TO";
+pub const EMAIL_FROM_KEY: &str = "EMAIL_FROM";
+pub const EMAIL_RATE_LIMIT_KEY: &str = "EMAIL_RATE_LIMIT";
+pub const EMAIL_QUIET_HOURS_KEY: &str = "EMAIL_QUIET_HOURS";
+
+pub const SIGNAL_URL_KEY: &str = "SIGNAL_URL";
+pub const SIGNAL_SENDER_KEY: &str = "SIGNAL_SENDER";
+pub const SIGNAL_RECIPIENTS_KEY: &str = "SIGNAL_RECIPIENTS";
+pub const SIGNAL_MESSAGE_PREFIX_KEY: &str = "SIGNAL_MESSAGE_PREFIX";
+pub const SIGNAL_RATE_LIMIT_KEY: &str = "SIGNAL_RATE_LIMIT";
+pub const SIGNAL_QUIET_HOURS_KEY: &str = "SIGNAL_QUIET_HOURS";
+
+/// Most pages a target can be spread over
+const MAX_PAGES: u32 = 100;
+
+/// Every key read from the environment, used by `gem export`
+pub const ENV_KEYS: &[&str] = &[
+    CONFIG_FILE_KEY,
+    TARGET_URL_KEY,
+    CONTENT_TYPE_KEY,
+    SELECTOR_KEY,
+    SEARCH_TEXT_KEY,
+    PATTERN_KEY,
+    PATTERN_FLAGS_KEY,
+    XPATH_KEY,
+    JSON_PATH_KEY,
+    CSV_FILTER_KEY,
+    MATCH_MODE_KEY,
+    NOTIFY_ON_KEY,
+    REQUIRE_CONSECUTIVE_KEY,
+    NOTIFY_RECOVERY_KEY,
+    REQUEST_METHOD_KEY,
+    REQUEST_HEADERS_KEY,
+    REQUEST_BODY_KEY,
+    REQUEST_COOKIES_KEY,
+    USER_AGENT_KEY,
+    RESPECT_ROBOTS_KEY,
+    SKIP_UNCHANGED_KEY,
+    NEXT_PAGE_SELECTOR_KEY,
+    PAGE_URLS_KEY,
+    MAX_PAGES_KEY,
+    PROXY_KEY,
+    CA_CERT_KEY,
+    CLIENT_CERT_KEY,
+    CLIENT_KEY_KEY,
+    RESOLVE_HOSTS_KEY,
+    INSECURE_TLS_KEY,
+    CONNECT_TIMEOUT_S_KEY,
+    TIMEOUT_S_KEY,
+    RETRIES_KEY,
+    RETRY_DELAY_S_KEY,
+    RETRY_STATUSES_KEY,
+    LOGIN_URL_KEY,
+    LOGIN_SUBMIT_URL_KEY,
+    LOGIN_CSRF_SELECTOR_KEY,
+    LOGIN_CSRF_FIELD_KEY,
+    LOGIN_USERNAME_FIELD_KEY,
+    LOGIN_PASSWORD_FIELD_KEY,
+    LOGIN_USERNAME_KEY,
+    LOGIN_PASSWORD_KEY,
+    SCHEDULE_KEY,
+    CHECK_INTERVAL_S_KEY,
+    JITTER_S_KEY,
+    NOTIFICATION_TYPE_KEY,
+    NOTIFICATION_MAX_PER_INTERVAL_KEY,
+    NOTIFICATION_INTERVAL_S_KEY,
+    NOTIFICATION_WRITE_DIR_KEY,
+    STATE_STORE_KEY,
+    STATE_DB_KEY,
+    HOST_DELAY_S_KEY,
+    HOST_CONCURRENCY_KEY,
+    DEBUG_KEY,
+    PREVENT_EMAIL_KEY,
+    PREVENT_MESSAGE_KEY,
+    SMTP_RELAY_KEY,
+    SMTP_USER_KEY,
+    SMTP_PASS_KEY,
+    EMAIL_TO_KEY,
+    EMAIL_FROM_KEY,
+    EMAIL_RATE_LIMIT_KEY,
+    EMAIL_QUIET_HOURS_KEY,
+    SIGNAL_URL_KEY,
+    SIGNAL_SENDER_KEY,
+    SIGNAL_RECIPIENTS_KEY,
+    SIGNAL_MESSAGE_PREFIX_KEY,
+    SIGNAL_RATE_LIMIT_KEY,
+    SIGNAL_QUIET_HOURS_KEY,
+];
+
+/// Keys that shouldn't end up in plain config when the deployment has somewhere better for them
+pub const SECRET_KEYS: &[&str] = &[
+    REQUEST_HEADERS_KEY,
+    REQUEST_COOKIES_KEY,
+    PROXY_KEY,
+    LOGIN_USERNAME_KEY,
+    LOGIN_PASSWORD_KEY,
+    SMTP_USER_KEY,
+    SMTP_PASS_KEY,
+    SIGNAL_URL_KEY,
+    SIGNAL_SENDER_KEY,
+    SIGNAL_RECIPIENTS_KEY,
+];
+
+/// Env keys holding the path of a file the target reads, so a deployment has to mount it
+pub const FILE_KEYS: &[&str] = &[CA_CERT_KEY, CLIENT_CERT_KEY, CLIENT_KEY_KEY];
+
+/// Config file keys holding credentials, a file using any of them is a secret as a whole
+pub const SECRET_TARGET_KEYS: &[&str] = &[
+    "headers",
+    "cookies",
+    "proxy",
+    "signal_url",
+    "signal_sender",
+    "signal_recipients",
+];
+
+/// Config file keys holding the path of a file the target reads, e.g. a mounted secret
+pub const FILE_TARGET_KEYS: &[&str] = &[
+    "ca_cert",
+    "client_cert",
+    "client_key",
+    "login_username_file",
+    "login_password_file",
+];
+
+/// A single watch target: where to look, what to look for and who to tell.
+#[derive(Builder, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Optional label used in logs, defaults to the target url
+    #[builder(default)]
+    #[serde(default)]
+    pub name: Option<String>,
+
+    pub content_type: ContentType,
+    pub url: Url,
+    pub search_terms: Option<Vec<String>>,
+    pub selector: Option<String>,
+    /// Regex for the regex content type, named groups are reported as fields
+    #[builder(default)]
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Any of `i` (case-insensitive), `m` (multiline), `s` (dot matches new line), `x` (verbose)
+    #[builder(default)]
+    #[serde(default)]
+    pub pattern_flags: Option<String>,
+    /// XPath 1.0 expression for the xpath content type
+    #[builder(default)]
+    #[serde(default)]
+    pub xpath: Option<String>,
+    /// JSONPath query for the json content type
+    #[builder(default)]
+    #[serde(default)]
+    pub json_path: Option<String>,
+    /// Row filter for the csv content type, e.g. `status == "in stock" && qty > 0`
+    #[builder(default)]
+    #[serde(default)]
+    pub csv_filter: Option<String>,
+    /// Whether to notify when the matcher finds something or when it finds nothing
+    #[builder(default)]
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    /// Whether every run with matches notifies or only runs where the matches changed
+    #[builder(default)]
+    #[serde(default)]
+    pub notify_on: NotifyOn,
+    /// Runs in a row that must agree before notifying, to ride out pages that flicker
+    #[builder(default)]
+    #[serde(default)]
+    pub require_consecutive: Option<u32>,
+    /// Also send an all clear once the target stops matching, or in absent mode matches again
+    #[builder(default)]
+    #[serde(default)]
+    pub notify_recovery: bool,
+
+    /// HTTP method used to fetch the page, GET when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Extra request headers e.g. `Accept-Language` or `Authorization`
+    #[builder(default)]
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Sent as is, set a `Content-Type` header to go with it
+    #[builder(default)]
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Cookies to start the target's cookie jar with
+    #[builder(default)]
+    #[serde(default)]
+    pub cookies: BTreeMap<String, String>,
+    /// Defaults to Googlebot's, which tends to get a server-side rendered page
+    #[builder(default)]
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Skip pages the site's robots.txt doesn't allow for `user_agent`
+    #[builder(default)]
+    #[serde(default)]
+    pub respect_robots: bool,
+    /// Send `If-None-Match`/`If-Modified-Since` and skip the run when the page hasn't changed
+    #[builder(default)]
+    #[serde(default)]
+    pub skip_unchanged: bool,
+    /// Link to the next page of results e.g. `a[rel=next]`, followed up to `max_pages`
+    #[builder(default)]
+    #[serde(default)]
+    pub next_page_selector: Option<String>,
+    /// Further pages after `url`, `{page}` is replaced with 2 up to `max_pages`
+    #[builder(default)]
+    #[serde(default)]
+    pub page_urls: Vec<String>,
+    /// Most pages fetched per check including the first, 10 when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub max_pages: Option<u32>,
+    /// `http://`, `https://` or `socks5://` proxy to fetch through, credentials can go in the url
+    #[builder(default)]
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// PEM file of extra CA certificates to trust, e.g. for internal hosts
+    #[builder(default)]
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    /// PEM client certificate file, needs `client_key`
+    #[builder(default)]
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PEM PKCS#8 private key file for `client_cert`
+    #[builder(default)]
+    #[serde(default)]
+    pub client_key: Option<String>,
+    /// Hosts to connect to at a fixed `ip:port` instead of looking them up in DNS, like curl's
+    /// `--resolve`. Certificates are checked as usual, it doesn't pin them
+    #[builder(default)]
+    #[serde(default)]
+    pub resolve_hosts: BTreeMap<String, String>,
+    /// Accept any certificate, only for lab hosts with self-signed ones
+    #[builder(default)]
+    #[serde(default)]
+    pub insecure_tls: bool,
+    /// Seconds to wait for a connection, 10 when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub connect_timeout_s: Option<u64>,
+    /// Seconds allowed for fetching each page, every retry and the waits between them included,
+    /// 30 when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub timeout_s: Option<u64>,
+    /// Further attempts after a failed fetch, 2 when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// Delay before the first retry, doubling for each one after, 1 when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub retry_delay_s: Option<u64>,
+    /// Response statuses worth another attempt, 429, 500, 502, 503 and 504 when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub retry_statuses: Option<Vec<u16>>,
+    /// Login form to sign in through before fetching, the session is kept between runs
+    #[builder(default)]
+    #[serde(default)]
+    pub login_url: Option<Url>,
+    /// Where the login form posts to, `login_url` when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub login_submit_url: Option<Url>,
+    /// Element on the login page holding the CSRF token, e.g. `input[name=csrf_token]`
+    #[builder(default)]
+    #[serde(default)]
+    pub login_csrf_selector: Option<String>,
+    /// Form field to send the CSRF token as, the element's `name` when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub login_csrf_field: Option<String>,
+    /// Form field for the username, `username` when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub login_username_field: Option<String>,
+    /// Form field for the password, `password` when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub login_password_field: Option<String>,
+    /// File holding the username, e.g. a mounted secret
+    #[builder(default)]
+    #[serde(default)]
+    pub login_username_file: Option<String>,
+    /// File holding the password, e.g. a mounted secret
+    #[builder(default)]
+    #[serde(default)]
+    pub login_password_file: Option<String>,
+    /// From `LOGIN_USERNAME`, file targets use `login_username_file`
+    #[builder(default)]
+    #[serde(skip)]
+    pub login_username: Option<String>,
+    /// From `LOGIN_PASSWORD`, file targets use `login_password_file`
+    #[builder(default)]
+    #[serde(skip)]
+    pub login_password: Option<String>,
+
+    pub email_to: Option<Mailbox>,
+    pub email_from: Option<Mailbox>,
+    /// e.g. `10/1h`, the `NOTIFICATION_*` limit is used when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub email_rate_limit: Option<RateLimit>,
+    /// e.g. `22:00-07:00 Europe/London`, emails found in the window are sent as one summary after
+    #[builder(default)]
+    #[serde(default)]
+    pub email_quiet_hours: Option<QuietHours>,
+
+    pub signal_url: Option<Url>,
+    #[serde(rename = "signal_message_prefix")]
+    pub signal_message: Option<String>,
+    #[serde(default)]
+    pub signal_recipients: Vec<String>,
+    pub signal_sender: Option<String>,
+    /// e.g. `1/1h`, the `NOTIFICATION_*` limit is used when unset
+    #[builder(default)]
+    #[serde(default)]
+    pub signal_rate_limit: Option<RateLimit>,
+    /// e.g. `22:00-07:00 Europe/London`, messages found in the window are sent as one summary after
+    #[builder(default)]
+    #[serde(default)]
+    pub signal_quiet_hours: Option<QuietHours>,
+
+    pub notification_types: Vec<NotificationType>,
+
+    /// Cron expression used by `gem daemon`, 5 field (minute first) or 6 field (second first),
+    /// with weekdays numbered 0 to 6 from Sunday as in crontab
+    #[builder(default)]
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Fixed number of seconds between checks in `gem daemon`, used when no schedule is set
+    #[builder(default)]
+    #[serde(default)]
+    pub check_interval_s: Option<u64>,
+    /// Upper bound of a random delay added to each scheduled check
+    #[builder(default)]
+    #[serde(default)]
+    pub jitter_s: Option<u64>,
+}
+
+impl Config {
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.url.to_string())
+    }
+
+    /// Stable id used to key the target's saved state, from the name if set or the url otherwise.
+    /// Urls that only differ in characters the id can't hold, e.g. `?a=b` and `/a/b`, are told
+    /// apart by a hash of the whole url on the end.
+    pub fn id(&self) -> String {
+        match &self.name {
+            Some(name) => sanitize_id(name),
+            None => format!(
+                "{}-{}",
+                sanitize_id(&format!(
+                    "{}{}",
+                    self.url.host_str().unwrap_or_default(),
+                    self.url.path()
+                )),
+                &content_hash(self.url.as_str())[..8]
+            ),
+        }
+    }
+
+    /// Whether matches come from more than the one page
+    pub fn is_paginated(&self) -> bool {
+        self.next_page_selector.is_some() || !self.page_urls.is_empty()
+    }
+
+    /// The username and password to log in with, read from their files each time so rotated
+    /// secrets are picked up.
+    pub fn login_credentials(&self) -> GemResult<(String, String)> {
+        let read = |key: &str, value: &Option<String>, path: &Option<String>| match (value, path) {
+            (Some(value), _) => Ok(value.clone()),
+            (None, Some(path)) => read_secret_file(path).map_err(|e| {
+                GemError::Config(format!("Unable to read {} from {}: {}", key, path, e))
+            }),
+            (None, None) => Err(GemError::Config(format!("Need {} to log in", key))),
+        };
+
+        Ok((
+            read(
+                "login_username_file",
+                &self.login_username,
+                &self.login_username_file,
+            )?,
+            read(
+                "login_password_file",
+                &self.login_password,
+                &self.login_password_file,
+            )?,
+        ))
+    }
+
+    /// The channel's own rate limit, if it has one
+    pub fn rate_limit(&self, channel: &NotificationType) -> Option<&RateLimit> {
+        match channel {
+            NotificationType::Email => self.email_rate_limit.as_ref(),
+            NotificationType::Signal => self.signal_rate_limit.as_ref(),
+        }
+    }
+
+    /// The channel's quiet hours, if it has any
+    pub fn quiet_hours(&self, channel: &NotificationType) -> Option<&QuietHours> {
+        match channel {
+            NotificationType::Email => self.email_quiet_hours.as_ref(),
+            NotificationType::Signal => self.signal_quiet_hours.as_ref(),
+        }
+    }
+
+    /// What the target looks for, for messages
+    pub fn matcher(&self) -> String {
+        let matcher = match self.content_type {
+            ContentType::Html => self
+                .selector
+                .as_ref()
+                .map(|selector| ("selector", selector.clone())),
+            ContentType::Text => self
+                .search_terms
+                .as_ref()
+                .map(|search_terms| ("search text", search_terms.join(", "))),
+            ContentType::Regex => self
+                .pattern
+                .as_ref()
+                .map(|pattern| ("pattern", pattern.clone())),
+            ContentType::XPath => self.xpath.as_ref().map(|xpath| ("XPath", xpath.clone())),
+            ContentType::Json => self
+                .json_path
+                .as_ref()
+                .map(|json_path| ("JSONPath", json_path.clone())),
+            ContentType::Csv => self
+                .csv_filter
+                .as_ref()
+                .map(|csv_filter| ("CSV filter", csv_filter.clone())),
+        };
+
+        matcher.map_or(self.content_type.to_string(), |(kind, value)| {
+            format!("{} '{}'", kind, value)
+        })
+    }
+}
+
+/// Shape of the file pointed at by `CONFIG_FILE`, one `[[target]]` table per watch target.
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(rename = "target", default)]
+    targets: Vec<toml::Table>,
+}
+
+/// Keeps the characters that are safe in a file name, lowercased, and swaps the rest for `_`.
+fn sanitize_id(source: &str) -> String {
+    source
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .to_string()
+}
+
+/// Every problem found with a single target's config.
+pub struct TargetReport {
+    pub label: String,
+    pub problems: Vec<String>,
+}
+
+/// What `check_configs` found, per target and in the settings every target shares.
+pub struct ConfigReport {
+    pub targets: Vec<TargetReport>,
+    pub shared_problems: Vec<String>,
+}
+
+/// Loads every target from the config file if given, otherwise falls back to a single
+/// target built from the environment.
+pub fn load_configs(config_file: Option<&str>) -> GemResult<Vec<Config>> {
+    let targets = read_targets(config_file)?;
+    check_reports(targets.iter().map(|(report, _)| report))?;
+    if let Some(problem) = check_shared_settings().into_iter().next() {
+        return Err(GemError::Config(problem));
+    }
+
+    Ok(targets
+        .into_iter()
+        .filter_map(|(_, config)| config)
+        .collect())
+}
+
+/// Loads every target from a config file like `load_configs` but without logging, along with
+/// the table each one was read from so it can be written back out.
+pub fn load_config_tables(path: &str) -> GemResult<Vec<(Config, toml::Table)>> {
+    let tables = parse_config_file(path)?;
+    let mut targets = tables
+        .iter()
+        .enumerate()
+        .map(|(index, table)| read_file_target(index, table.clone()))
+        .collect::<Vec<(TargetReport, Option<Config>)>>();
+    check_duplicate_ids(&mut targets);
+    check_reports(targets.iter().map(|(report, _)| report))?;
+    if let Some(problem) = check_shared_settings().into_iter().next() {
+        return Err(GemError::Config(problem));
+    }
+
+    Ok(targets
+        .into_iter()
+        .zip(tables)
+        .filter_map(|((_, config), table)| config.map(|config| (config, table)))
+        .collect())
+}
+
+/// Fails with every problem in the reports, if there are any.
+fn check_reports<'a>(reports: impl Iterator<Item = &'a TargetReport>) -> GemResult<()> {
+    let problems = reports
+        .flat_map(|report| {
+            report
+                .problems
+                .iter()
+                .map(|problem| format!("{}: {}", report.label, problem))
+        })
+        .collect::<Vec<String>>();
+
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(GemError::Config(problems.join("\n"))),
+    }
+}
+
+/// Checks every target and the settings they share without doing any network I/O, reporting
+/// all problems rather than stopping at the first one.
+pub fn check_configs(config_file: Option<&str>) -> GemResult<ConfigReport> {
+    Ok(ConfigReport {
+        targets: read_targets(config_file)?
+            .into_iter()
+            .map(|(report, _)| report)
+            .collect(),
+        shared_problems: check_shared_settings(),
+    })
+}
+
+/// The process-wide settings that are otherwise only read when they're first needed.
+fn check_shared_settings() -> Vec<String> {
+    [
+        RateLimit::from_env().map(|_| ()),
+        StoreKind::from_env().map(|_| ()),
+        HostLimits::from_env().map(|_| ()),
+        notification_write_dir().map(|_| ()),
+    ]
+    .into_iter()
+    .filter_map(|result| result.err())
+    .map(|e| e.message().to_string())
+    .collect()
+}
+
+fn read_targets(config_file: Option<&str>) -> GemResult<Vec<(TargetReport, Option<Config>)>> {
+    match config_file {
+        Some(path) => {
+            let mut targets = read_config_file(path)?;
+            check_duplicate_ids(&mut targets);
+            Ok(targets)
+        }
+        None => {
+            let mut problems = Vec::new();
+            let config = read_env_config(&mut problems);
+            let report = TargetReport {
+                label: config
+                    .as_ref()
+                    .map_or(TARGET_URL_KEY.to_string(), |config| config.label()),
+                problems,
+            };
+            Ok(vec![(report, config)])
+        }
+    }
+}
+
+fn read_config_file(path: &str) -> GemResult<Vec<(TargetReport, Option<Config>)>> {
+    println!("Loading targets from {}", path);
+
+    let targets = parse_config_file(path)?;
+
+    println!("Found {} target(s)", targets.len());
+
+    Ok(targets
+        .into_iter()
+        .enumerate()
+        .map(|(index, table)| read_file_target(index, table))
+        .collect())
+}
+
+/// Targets keep their state under their id so two with the same one would share it, which is
+/// reported against the second.
+fn check_duplicate_ids(targets: &mut [(TargetReport, Option<Config>)]) {
+    let mut seen: Vec<(String, String)> = Vec::new();
+
+    for (report, config) in targets.iter_mut() {
+        let Some(config) = config else {
+            continue;
+        };
+
+        let id = config.id();
+        match seen.iter().find(|(seen_id, _)| *seen_id == id) {
+            Some((_, label)) => report.problems.push(format!(
+                "Has the same id '{}' as {}, give one of them a different name",
+                id, label
+            )),
+            None => seen.push((id, report.label.clone())),
+        }
+    }
+}
+
+/// The `[[target]]` tables in a config file, there has to be at least one.
+fn parse_config_file(path: &str) -> GemResult<Vec<toml::Table>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| GemError::Config(format!("Unable to read {}: {}", path, e)))?;
+    let config_file = toml::from_str::<ConfigFile>(&contents)
+        .map_err(|e| GemError::Config(format!("Unable to parse {}: {}", path, e)))?;
+
+    if config_file.targets.is_empty() {
+        return Err(GemError::Config(format!(
+            "No [[target]] entries found in {}",
+            path
+        )));
+    }
+
+    Ok(config_file.targets)
+}
+
+fn read_file_target(index: usize, table: toml::Table) -> (TargetReport, Option<Config>) {
+    let label = ["name", "url"]
+        .iter()
+        .find_map(|key| table.get(*key).and_then(|value| value.as_str()))
+        .map_or(format!("target {}", index + 1), |label| label.to_string());
+
+    // serde stops at the first bad field so look over the usual suspects too, such as a bad url
+    // next to an unknown key
+    let mut problems = check_file_values(&table);
+
+    let config = match Config::deserialize(toml::Value::Table(table)) {
+        Ok(config) => {
+            validate_target(&config, &mut problems);
+            Some(config)
+        }
+        Err(error) => {
+            problems.push(error.message().to_string());
+            None
+        }
+    };
+
+    (TargetReport { label, problems }, config)
+}
+
+fn check_file_values(table: &toml::Table) -> Vec<String> {
+    let mut problems = Vec::new();
+    let string_value = |key: &str| table.get(key).and_then(|value| value.as_str());
+
+    match string_value("url") {
+        Some(url) => check_url("url", url, &mut problems),
+        None => problems.push("Need url".to_string()),
+    }
+    for key in ["signal_url", "login_url", "login_submit_url"] {
+        if let Some(url) = string_value(key) {
+            check_url(key, url, &mut problems);
+        }
+    }
+
+    match string_value("content_type") {
+        Some(content_type) => {
+            if ContentType::try_from(&content_type.to_string()).is_err() {
+                problems.push(format!("Unknown content_type {}", content_type));
+            }
+        }
+        None => problems.push("Need content_type".to_string()),
+    }
+
+    match table
+        .get("notification_types")
+        .and_then(|value| value.as_array())
+    {
+        Some(notification_types) => {
+            for entry in notification_types {
+                let entry = entry.as_str().unwrap_or_default();
+                if NotificationType::try_from(entry).is_err() {
+                    problems.push(format!("Unknown notification type '{}'", entry));
+                }
+            }
+        }
+        None => problems.push("Need notification_types e.g. [\"signal\", \"email\"]".to_string()),
+    }
+
+    for key in ["email_to", "email_from"] {
+        if let Some(mailbox) = string_value(key) {
+            check_mailbox(key, mailbox, &mut problems);
+        }
+    }
+
+    if let Some(selector) = string_value("selector") {
+        check_selector(selector, &mut problems);
+    }
+
+    if let Some(method) = string_value("method") {
+        check_method(method, &mut problems);
+    }
+    if let Some(headers) = table.get("headers").and_then(|value| value.as_table()) {
+        for (name, value) in headers {
+            check_header(name, value.as_str().unwrap_or_default(), &mut problems);
+        }
+    }
+    if let Some(cookies) = table.get("cookies").and_then(|value| value.as_table()) {
+        for (name, value) in cookies {
+            check_cookie(name, value.as_str().unwrap_or_default(), &mut problems);
+        }
+    }
+    if let Some(user_agent) = string_value("user_agent") {
+        check_header("User-Agent", user_agent, &mut problems);
+    }
+
+    if let Some(pattern) = string_value("pattern") {
+        check_pattern(pattern, string_value("pattern_flags"), &mut problems);
+    }
+
+    if let Some(Err(e)) = string_value("xpath").map(compile_xpath) {
+        problems.push(e);
+    }
+
+    if let Some(Err(e)) = string_value("json_path").map(compile_json_path) {
+        problems.push(e);
+    }
+
+    if let Some(Err(e)) = string_value("csv_filter").map(parse_filter) {
+        problems.push(e);
+    }
+
+    if let Some(match_mode) = string_value("match_mode") {
+        if MatchMode::try_from(match_mode).is_err() {
+            problems.push(format!(
+                "Unknown match_mode '{}', expected present or absent",
+                match_mode
+            ));
+        }
+    }
+
+    for key in ["email_rate_limit", "signal_rate_limit"] {
+        if let Some(Err(e)) = string_value(key).map(RateLimit::try_from) {
+            problems.push(e);
+        }
+    }
+
+    for key in ["email_quiet_hours", "signal_quiet_hours"] {
+        if let Some(Err(e)) = string_value(key).map(QuietHours::try_from) {
+            problems.push(e);
+        }
+    }
+
+    if let Some(notify_on) = string_value("notify_on") {
+        if NotifyOn::try_from(notify_on).is_err() {
+            problems.push(format!(
+                "Unknown notify_on '{}', expected every or changes",
+                notify_on
+            ));
+        }
+    }
+
+    if let Some(Err(e)) = string_value("schedule").map(parse_schedule) {
+        problems.push(e);
+    }
+
+    problems
+}
+
+/// File targets skip the per-key checks done in `read_env_config` so apply the same rules here,
+/// the values themselves are checked by `check_file_values`.
+fn validate_target(config: &Config, problems: &mut Vec<String>) {
+    match config.content_type {
+        ContentType::Html => {
+            if config.selector.is_none() {
+                problems.push("Please supply selector for HTML content type".to_string());
+            }
+        }
+        ContentType::Text => {
+            if config.search_terms.is_none() {
+                problems.push("Please supply search_terms for text content type".to_string());
+            }
+        }
+        ContentType::Regex => {
+            if config.pattern.is_none() {
+                problems.push("Please supply pattern for regex content type".to_string());
+            }
+        }
+        ContentType::XPath => {
+            if config.xpath.is_none() {
+                problems.push("Please supply xpath for XPath content type".to_string());
+            }
+        }
+        ContentType::Json => {
+            if config.json_path.is_none() {
+                problems.push("Please supply json_path for JSON content type".to_string());
+            }
+        }
+        ContentType::Csv => {
+            if config.csv_filter.is_none() {
+                problems.push("Please supply csv_filter for CSV content type".to_string());
+            }
+        }
+    }
+
+    if let Some(require_consecutive) = config.require_consecutive {
+        check_require_consecutive(require_consecutive, problems);
+    }
+
+    for (key, timeout_s) in [
+        ("connect_timeout_s", config.connect_timeout_s),
+        ("timeout_s", config.timeout_s),
+        ("check_interval_s", config.check_interval_s),
+    ] {
+        if timeout_s == Some(0) {
+            problems.push(format!("{} must be more than 0", key));
+        }
+    }
+    if let Some(retry_statuses) = &config.retry_statuses {
+        check_retry_statuses(retry_statuses, problems);
+    }
+
+    check_pagination(config, problems);
+    check_transport(config, problems);
+    check_login(config, problems);
+
+    if config.notification_types.contains(&NotificationType::Email) {
+        if config.email_to.is_none() {
+            problems.push("Need email_to for Email notifs".to_string());
+        }
+        if config.email_from.is_none() {
+            problems.push("Need email_from for Email notifs".to_string());
+        }
+
+        check_smtp_vars(problems);
+    }
+
+    if config
+        .notification_types
+        .contains(&NotificationType::Signal)
+    {
+        if config.signal_url.is_none() {
+            problems.push("Need signal_url for Signal notifs".to_string());
+        }
+        if config.signal_sender.is_none() {
+            problems.push("Need signal_sender for Signal notifs".to_string());
+        }
+        if config.signal_recipients.is_empty() {
+            problems.push("Need signal_recipients for Signal notifs".to_string());
+        }
+    }
+}
+
+fn check_url(key: &str, value: &str, problems: &mut Vec<String>) {
+    if let Err(e) = Url::parse(value) {
+        problems.push(format!("Invalid {} '{}': {}", key, value, e));
+    }
+}
+
+fn check_mailbox(key: &str, value: &str, problems: &mut Vec<String>) {
+    if value.parse::<Mailbox>().is_err() {
+        problems.push(format!(
+            "Invalid {} '{}', need user of form: 'User <user@example.com>'",
+            key, value
+        ));
+    }
+}
+
+fn check_require_consecutive(require_consecutive: u32, problems: &mut Vec<String>) {
+    if require_consecutive == 0 || require_consecutive as usize > MAX_RUNS {
+        problems.push(format!(
+            "Invalid require_consecutive {}, expected 1 to {}",
+            require_consecutive, MAX_RUNS
+        ));
+    }
+}
+
+fn check_method(method: &str, problems: &mut Vec<String>) {
+    if let Err(e) = parse_method(method) {
+        problems.push(e.message().to_string());
+    }
+}
+
+fn check_header(name: &str, value: &str, problems: &mut Vec<String>) {
+    if HeaderName::from_bytes(name.as_bytes()).is_err() {
+        problems.push(format!("Invalid header name '{}'", name));
+    }
+    if HeaderValue::from_str(value).is_err() {
+        problems.push(format!("Invalid value for header {}", name));
+    }
+}
+
+fn check_cookie(name: &str, value: &str, problems: &mut Vec<String>) {
+    let is_valid_name = !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "=;,".contains(c));
+    if !is_valid_name {
+        problems.push(format!("Invalid cookie name '{}'", name));
+    }
+    if value.chars().any(|c| c.is_control() || c == ';') {
+        problems.push(format!("Invalid value for cookie {}", name));
+    }
+}
+
+fn check_pagination(config: &Config, problems: &mut Vec<String>) {
+    if config.next_page_selector.is_some() && !config.page_urls.is_empty() {
+        problems.push("Use either next_page_selector or page_urls, not both".to_string());
+    }
+    if let Some(next_page_selector) = &config.next_page_selector {
+        check_selector(next_page_selector, problems);
+    }
+    if let Err(e) = template_urls(config) {
+        problems.push(e.message().to_string());
+    }
+    if let Some(max_pages) = config
+        .max_pages
+        .filter(|max_pages| !(1..=MAX_PAGES).contains(max_pages))
+    {
+        problems.push(format!(
+            "Invalid max_pages {}, expected 1 to {}",
+            max_pages, MAX_PAGES
+        ));
+    }
+}
+
+/// Proxy and TLS settings, the files are only checked for being there.
+fn check_transport(config: &Config, problems: &mut Vec<String>) {
+    if let Some(proxy) = &config.proxy {
+        if let Err(e) = reqwest::Proxy::all(proxy) {
+            problems.push(format!("Invalid proxy: {}", e));
+        }
+    }
+
+    for (key, path) in [
+        ("ca_cert", &config.ca_cert),
+        ("client_cert", &config.client_cert),
+        ("client_key", &config.client_key),
+    ] {
+        if let Some(path) = path.as_ref().filter(|path| !Path::new(path).is_file()) {
+            problems.push(format!("No {} file at {}", key, path));
+        }
+    }
+    if config.client_cert.is_some() != config.client_key.is_some() {
+        problems.push("client_cert and client_key need to be set together".to_string());
+    }
+
+    for (host, address) in &config.resolve_hosts {
+        if address.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "Invalid address '{}' to resolve {} to, expected ip:port",
+                address, host
+            ));
+        }
+    }
+}
+
+fn check_login(config: &Config, problems: &mut Vec<String>) {
+    let is_login_set = config.login_submit_url.is_some()
+        || config.login_csrf_selector.is_some()
+        || config.login_username_file.is_some()
+        || config.login_password_file.is_some()
+        || config.login_username.is_some()
+        || config.login_password.is_some();
+    if config.login_url.is_none() && is_login_set {
+        problems.push("The login settings need login_url".to_string());
+    }
+
+    for (key, env_key, value, path) in [
+        (
+            "login_username_file",
+            LOGIN_USERNAME_KEY,
+            &config.login_username,
+            &config.login_username_file,
+        ),
+        (
+            "login_password_file",
+            LOGIN_PASSWORD_KEY,
+            &config.login_password,
+            &config.login_password_file,
+        ),
+    ] {
+        match (value, path) {
+            (None, None) if config.login_url.is_some() => {
+                problems.push(format!("Need {} or {} to log in", key, env_key))
+            }
+            (None, Some(path)) if !Path::new(path).is_file() => {
+                problems.push(format!("No {} file at {}", key, path))
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(login_csrf_selector) = &config.login_csrf_selector {
+        check_selector(login_csrf_selector, problems);
+    }
+}
+
+fn check_retry_statuses(retry_statuses: &[u16], problems: &mut Vec<String>) {
+    for status in retry_statuses {
+        if !(100..=599).contains(status) {
+            problems.push(format!("Invalid retry status {}", status));
+        }
+    }
+}
+
+fn check_selector(selector: &str, problems: &mut Vec<String>) {
+    if let Err(e) = Selector::parse(selector) {
+        problems.push(format!("Unable to parse selector '{}': {:?}", selector, e));
+    }
+}
+
+fn check_pattern(pattern: &str, flags: Option<&str>, problems: &mut Vec<String>) {
+    if let Err(e) = compile_pattern(pattern, flags) {
+        problems.push(e);
+    }
+}
+
+fn check_smtp_vars(problems: &mut Vec<String>) {
+    for (key, message) in [
+        (SMTP_RELAY_KEY, "Need SMTP_RELAY url"),
+        (SMTP_USER_KEY, "Need SMTP_USER username"),
+        (SMTP_PASS_KEY, "Need SMTP_PASS password"),
+    ] {
+        read_required_var(key, message, problems);
+    }
+}
+
+/// Directory where notification state is kept, `NOTIFICATION_WRITE_DIR` or the working directory.
+pub fn notification_write_dir() -> GemResult<String> {
+    Ok(env_var(NOTIFICATION_WRITE_DIR_KEY)?.unwrap_or(DEFAULT_NOTIFICATION_WRITE_DIR.to_string()))
+}
+
+/// Reads `key` from the environment, or from the file named by `<key>_FILE` so secrets can be
+/// mounted (e.g. Docker or Kubernetes secrets) instead of showing up in `docker inspect`.
+pub fn env_var(key: &str) -> GemResult<Option<String>> {
+    let file_key = format!("{}{}", key, FILE_SUFFIX);
+
+    match (env::var(key).ok(), env::var(&file_key).ok()) {
+        (Some(_), Some(_)) => Err(GemError::Config(format!(
+            "Both {} and {} are set, only use one",
+            key, file_key
+        ))),
+        (Some(value), None) => Ok(Some(value)),
+        (None, Some(path)) => read_secret_file(&path)
+            .map(Some)
+            .map_err(|e| GemError::Config(format!("Unable to read {} from {}: {}", key, path, e))),
+        (None, None) => Ok(None),
+    }
+}
+
+fn read_secret_file(path: &str) -> std::io::Result<String> {
+    // secret files usually end with a newline that isn't part of the value
+    fs::read_to_string(path).map(|contents| contents.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Reads a required env var, using `message` to explain what's missing.
+pub fn require_var(key: &str, message: &str) -> GemResult<String> {
+    env_var(key)?.ok_or_else(|| GemError::Config(message.to_string()))
+}
+
+/// Like `env_var` but records the problem and carries on.
+fn read_var(key: &str, problems: &mut Vec<String>) -> Option<String> {
+    env_var(key).unwrap_or_else(|e| {
+        problems.push(e.message().to_string());
+        None
+    })
+}
+
+/// Like `require_var` but records the problem and carries on.
+fn read_required_var(key: &str, message: &str, problems: &mut Vec<String>) -> Option<String> {
+    match env_var(key) {
+        Ok(Some(value)) => Some(value),
+        Ok(None) => {
+            problems.push(message.to_string());
+            None
+        }
+        Err(e) => {
+            problems.push(e.message().to_string());
+            None
+        }
+    }
+}
+
+fn parse_seconds_var(key: &str, problems: &mut Vec<String>) -> Option<u64> {
+    let value = read_var(key, problems)?;
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(seconds),
+        Err(_) => {
+            problems.push(format!("Invalid number of seconds for {}: {}", key, value));
+            None
+        }
+    }
+}
+
+fn read_bool_var(key: &str, problems: &mut Vec<String>) -> Option<bool> {
+    let value = read_var(key, problems)?;
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => {
+            problems.push(format!(
+                "Invalid {} '{}', expected true or false",
+                key, value
+            ));
+            None
+        }
+    }
+}
+
+fn read_rate_limit_var(key: &str, problems: &mut Vec<String>) -> Option<RateLimit> {
+    let value = read_var(key, problems)?;
+    RateLimit::try_from(value.as_str())
+        .map_err(|e| problems.push(format!("{}: {}", key, e)))
+        .ok()
+}
+
+fn read_quiet_hours_var(key: &str, problems: &mut Vec<String>) -> Option<QuietHours> {
+    let value = read_var(key, problems)?;
+    QuietHours::try_from(value.as_str())
+        .map_err(|e| problems.push(format!("{}: {}", key, e)))
+        .ok()
+}
+
+/// Builds a single target from the environment, recording every problem found on the way.
+fn read_env_config(problems: &mut Vec<String>) -> Option<Config> {
+    let url = read_required_var(TARGET_URL_KEY, "Please define TARGET_URL in .env", problems)
+        .and_then(|url_string| {
+            println!("Polling {} ", &url_string);
+            Url::parse(&url_string)
+                .map_err(|e| problems.push(format!("Invalid URL {}: {}", url_string, e)))
+                .ok()
+        });
+
+    let content_type = read_required_var(
+        CONTENT_TYPE_KEY,
+        "Please define CONTENT_TYPE in .env",
+        problems,
+    )
+    .and_then(|content_type_string| {
+        ContentType::try_from(&content_type_string)
+            .map_err(|_| problems.push(format!("Unknown content type {}", content_type_string)))
+            .ok()
+    });
+
+    let mut config_builder = ConfigBuilder::default();
+
+    if let Some(match_mode) = read_var(MATCH_MODE_KEY, problems) {
+        match MatchMode::try_from(match_mode.as_str()) {
+            Ok(match_mode) => {
+                println!("notifying when matches are {}", match_mode);
+                config_builder.match_mode(match_mode);
+            }
+            Err(_) => problems.push(format!(
+                "Unknown MATCH_MODE '{}', expected present or absent",
+                match_mode
+            )),
+        }
+    }
+
+    if let Some(notify_on) = read_var(NOTIFY_ON_KEY, problems) {
+        match NotifyOn::try_from(notify_on.as_str()) {
+            Ok(notify_on) => {
+                println!("notifying on {}", notify_on);
+                config_builder.notify_on(notify_on);
+            }
+            Err(_) => problems.push(format!(
+                "Unknown NOTIFY_ON '{}', expected every or changes",
+                notify_on
+            )),
+        }
+    }
+
+    if let Some(require_consecutive) = read_var(REQUIRE_CONSECUTIVE_KEY, problems) {
+        match require_consecutive.parse::<u32>() {
+            Ok(require_consecutive) => {
+                check_require_consecutive(require_consecutive, problems);
+                config_builder.require_consecutive(Some(require_consecutive));
+            }
+            Err(_) => problems.push(format!(
+                "Invalid number for REQUIRE_CONSECUTIVE: {}",
+                require_consecutive
+            )),
+        }
+    }
+
+    if let Some(notify_recovery) = read_bool_var(NOTIFY_RECOVERY_KEY, problems) {
+        config_builder.notify_recovery(notify_recovery);
+    }
+    if let Some(skip_unchanged) = read_bool_var(SKIP_UNCHANGED_KEY, problems) {
+        config_builder.skip_unchanged(skip_unchanged);
+    }
+    if let Some(respect_robots) = read_bool_var(RESPECT_ROBOTS_KEY, problems) {
+        config_builder.respect_robots(respect_robots);
+    }
+
+    let method = read_var(REQUEST_METHOD_KEY, problems);
+    if let Some(method) = &method {
+        check_method(method, problems);
+    }
+
+    let headers = read_var(REQUEST_HEADERS_KEY, problems)
+        .map(|headers| {
+            headers
+                .split('|')
+                .filter(|header| !header.trim().is_empty())
+                .filter_map(|header| match header.split_once(':') {
+                    Some((name, value)) => {
+                        check_header(name.trim(), value.trim(), problems);
+                        Some((name.trim().to_string(), value.trim().to_string()))
+                    }
+                    None => {
+                        problems.push(format!(
+                            "Invalid header '{}' in REQUEST_HEADERS, expected Name: value",
+                            header.trim()
+                        ));
+                        None
+                    }
+                })
+                .collect::<BTreeMap<String, String>>()
+        })
+        .unwrap_or_default();
+
+    let cookies = read_var(REQUEST_COOKIES_KEY, problems)
+        .map(|cookies| {
+            cookies
+                .split(';')
+                .filter(|cookie| !cookie.trim().is_empty())
+                .filter_map(|cookie| match cookie.split_once('=') {
+                    Some((name, value)) => {
+                        check_cookie(name.trim(), value.trim(), problems);
+                        Some((name.trim().to_string(), value.trim().to_string()))
+                    }
+                    None => {
+                        problems.push(format!(
+                            "Invalid cookie '{}' in REQUEST_COOKIES, expected name=value",
+                            cookie.trim()
+                        ));
+                        None
+                    }
+                })
+                .collect::<BTreeMap<String, String>>()
+        })
+        .unwrap_or_default();
+
+    let user_agent = read_var(USER_AGENT_KEY, problems);
+    if let Some(user_agent) = &user_agent {
+        check_header("User-Agent", user_agent, problems);
+    }
+
+    let connect_timeout_s = parse_seconds_var(CONNECT_TIMEOUT_S_KEY, problems);
+    let timeout_s = parse_seconds_var(TIMEOUT_S_KEY, problems);
+    for (key, timeout_s) in [
+        (CONNECT_TIMEOUT_S_KEY, connect_timeout_s),
+        (TIMEOUT_S_KEY, timeout_s),
+    ] {
+        if timeout_s == Some(0) {
+            problems.push(format!("{} must be more than 0", key));
+        }
+    }
+
+    let retries = read_var(RETRIES_KEY, problems).and_then(|retries| {
+        retries
+            .parse::<u32>()
+            .map_err(|_| problems.push(format!("Invalid number for RETRIES: {}", retries)))
+            .ok()
+    });
+
+    let retry_statuses = read_var(RETRY_STATUSES_KEY, problems).and_then(|statuses| {
+        statuses
+            .split(',')
+            .map(|status| status.trim().parse::<u16>())
+            .collect::<Result<Vec<u16>, _>>()
+            .map_err(|_| problems.push(format!("Invalid status in RETRY_STATUSES: {}", statuses)))
+            .ok()
+    });
+    if let Some(retry_statuses) = &retry_statuses {
+        check_retry_statuses(retry_statuses, problems);
+    }
+
+    let resolve_hosts = read_var(RESOLVE_HOSTS_KEY, problems)
+        .map(|resolve_hosts| {
+            resolve_hosts
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+                .filter_map(|entry| match entry.split_once('=') {
+                    Some((host, address)) => {
+                        Some((host.trim().to_string(), address.trim().to_string()))
+                    }
+                    None => {
+                        problems.push(format!(
+                            "Invalid host '{}' in RESOLVE_HOSTS, expected host=ip:port",
+                            entry.trim()
+                        ));
+                        None
+                    }
+                })
+                .collect::<BTreeMap<String, String>>()
+        })
+        .unwrap_or_default();
+
+    let max_pages = read_var(MAX_PAGES_KEY, problems).and_then(|max_pages| {
+        max_pages
+            .parse::<u32>()
+            .map_err(|_| problems.push(format!("Invalid number for MAX_PAGES: {}", max_pages)))
+            .ok()
+    });
+    let page_urls = read_var(PAGE_URLS_KEY, problems)
+        .map(|page_urls| {
+            page_urls
+                .split(',')
+                .map(str::trim)
+                .filter(|page_url| !page_url.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    let mut read_url_var = |key: &str| {
+        read_var(key, problems).and_then(|url| {
+            Url::parse(&url)
+                .map_err(|e| problems.push(format!("Invalid {} {}: {}", key, url, e)))
+                .ok()
+        })
+    };
+    let login_url = read_url_var(LOGIN_URL_KEY);
+    let login_submit_url = read_url_var(LOGIN_SUBMIT_URL_KEY);
+
+    config_builder
+        .next_page_selector(read_var(NEXT_PAGE_SELECTOR_KEY, problems))
+        .page_urls(page_urls)
+        .max_pages(max_pages)
+        .proxy(read_var(PROXY_KEY, problems))
+        .ca_cert(read_var(CA_CERT_KEY, problems))
+        .client_cert(read_var(CLIENT_CERT_KEY, problems))
+        .client_key(read_var(CLIENT_KEY_KEY, problems))
+        .resolve_hosts(resolve_hosts)
+        .insecure_tls(read_bool_var(INSECURE_TLS_KEY, problems).unwrap_or_default())
+        .connect_timeout_s(connect_timeout_s)
+        .timeout_s(timeout_s)
+        .retries(retries)
+        .retry_delay_s(parse_seconds_var(RETRY_DELAY_S_KEY, problems))
+        .retry_statuses(retry_statuses)
+        .method(method)
+        .headers(headers)
+        .body(read_var(REQUEST_BODY_KEY, problems))
+        .cookies(cookies)
+        .user_agent(user_agent)
+        .login_url(login_url)
+        .login_submit_url(login_submit_url)
+        .login_csrf_selector(read_var(LOGIN_CSRF_SELECTOR_KEY, problems))
+        .login_csrf_field(read_var(LOGIN_CSRF_FIELD_KEY, problems))
+        .login_username_field(read_var(LOGIN_USERNAME_FIELD_KEY, problems))
+        .login_password_field(read_var(LOGIN_PASSWORD_FIELD_KEY, problems))
+        .login_username(read_var(LOGIN_USERNAME_KEY, problems))
+        .login_password(read_var(LOGIN_PASSWORD_KEY, problems));
+
+    let (search_terms, selector) = match content_type {
+        Some(ContentType::Html) => {
+            println!("for '{}' content", ContentType::Html);
+            let selector = read_required_var(
+                SELECTOR_KEY,
+                "Please supply SELECTOR in .env for HTML content type",
+                problems,
+            );
+            if let Some(selector) = &selector {
+                println!("using selector: {}", selector);
+                check_selector(selector, problems);
+            }
+            (None, selector)
+        }
+        Some(ContentType::Text) => {
+            println!("for '{}' content", ContentType::Text);
+            let search_terms = read_required_var(
+                SEARCH_TEXT_KEY,
+                "Please define SEARCH_TEXT in .env as comma separated entries",
+                problems,
+            )
+            .map(|search_text| {
+                println!("using search_text: {}", search_text);
+                search_text
+                    .split(',')
+                    .map(|term| term.to_owned())
+                    .collect::<Vec<String>>()
+            });
+            (search_terms, None)
+        }
+        Some(ContentType::Regex) => {
+            println!("for '{}' content", ContentType::Regex);
+            let pattern = read_required_var(
+                PATTERN_KEY,
+                "Please supply PATTERN in .env for regex content type",
+                problems,
+            );
+            let pattern_flags = read_var(PATTERN_FLAGS_KEY, problems);
+            if let Some(pattern) = &pattern {
+                println!("using pattern: {}", pattern);
+                check_pattern(pattern, pattern_flags.as_deref(), problems);
+            }
+            config_builder.pattern(pattern).pattern_flags(pattern_flags);
+            (None, None)
+        }
+        Some(ContentType::XPath) => {
+            println!("for '{}' content", ContentType::XPath);
+            let xpath = read_required_var(
+                XPATH_KEY,
+                "Please supply XPATH in .env for XPath content type",
+                problems,
+            );
+            if let Some(xpath) = &xpath {
+                println!("using xpath: {}", xpath);
+                if let Err(e) = compile_xpath(xpath) {
+                    problems.push(e);
+                }
+            }
+            config_builder.xpath(xpath);
+            (None, None)
+        }
+        Some(ContentType::Json) => {
+            println!("for '{}' content", ContentType::Json);
+            let json_path = read_required_var(
+                JSON_PATH_KEY,
+                "Please supply JSON_PATH in .env for JSON content type",
+                problems,
+            );
+            if let Some(json_path) = &json_path {
+                println!("using json_path: {}", json_path);
+                if let Err(e) = compile_json_path(json_path) {
+                    problems.push(e);
+                }
+            }
+            config_builder.json_path(json_path);
+            (None, None)
+        }
+        Some(ContentType::Csv) => {
+            println!("for '{}' content", ContentType::Csv);
+            let csv_filter = read_required_var(
+                CSV_FILTER_KEY,
+                "Please supply CSV_FILTER in .env for CSV content type",
+                problems,
+            );
+            if let Some(csv_filter) = &csv_filter {
+                println!("using csv_filter: {}", csv_filter);
+                if let Err(e) = parse_filter(csv_filter) {
+                    problems.push(e);
+                }
+            }
+            config_builder.csv_filter(csv_filter);
+            (None, None)
+        }
+        None => (None, None),
+    };
+
+    config_builder
+        .search_terms(search_terms)
+        .selector(selector)
+        .email_from(None)
+        .email_to(None)
+        .signal_url(None)
+        .signal_message(None)
+        .signal_recipients(Vec::new())
+        .signal_sender(None);
+
+    let notification_types = read_required_var(
+        NOTIFICATION_TYPE_KEY,
+        "Need NOTIFICATION_TYPE with comma separated types e.g signal,email",
+        problems,
+    )
+    .map(|notification_type_string| {
+        notification_type_string
+            .split(',')
+            .filter_map(|entry| {
+                NotificationType::try_from(entry)
+                    .map_err(|_| problems.push(format!("Unknown NOTIFICATION_TYPE {}", entry)))
+                    .ok()
+            })
+            .collect::<Vec<NotificationType>>()
+    })
+    .unwrap_or_default();
+
+    if notification_types.contains(&NotificationType::Email) {
+        println!("Config for Email notifs found");
+
+        let email_to = read_required_var(
+            EMAIL_TO_KEY,
+            "Need EMAIL_TO user email: 'User <user@example.com>'",
+            problems,
+        )
+        .and_then(|email_to_string| {
+            email_to_string
+                .parse::<Mailbox>()
+                .map_err(|_| check_mailbox(EMAIL_TO_KEY, &email_to_string, problems))
+                .ok()
+        });
+
+        let email_from = read_required_var(
+            EMAIL_FROM_KEY,
+            "Need EMAIL_FROM user email: 'App Name <app@example.com>'",
+            problems,
+        )
+        .and_then(|email_from_string| {
+            email_from_string
+                .parse::<Mailbox>()
+                .map_err(|_| check_mailbox(EMAIL_FROM_KEY, &email_from_string, problems))
+                .ok()
+        });
+
+        // doing this upfront so we can exit early
+        check_smtp_vars(problems);
+
+        config_builder
+            .email_to(email_to)
+            .email_from(email_from)
+            .email_rate_limit(read_rate_limit_var(EMAIL_RATE_LIMIT_KEY, problems))
+            .email_quiet_hours(read_quiet_hours_var(EMAIL_QUIET_HOURS_KEY, problems));
+    }
+
+    if notification_types.contains(&NotificationType::Signal) {
+        println!("Config for Signal notifs found");
+
+        let signal_url =
+            read_required_var(SIGNAL_URL_KEY, "Need SIGNAL_URL to send notifs", problems).and_then(
+                |signal_url_string| {
+                    Url::from_str(&signal_url_string)
+                        .map_err(|e| problems.push(format!("Error parsing signal URL: {}", e)))
+                        .ok()
+                },
+            );
+
+        let signal_message_prefix = read_var(SIGNAL_MESSAGE_PREFIX_KEY, problems);
+
+        let signal_recipients = read_required_var(
+            SIGNAL_RECIPIENTS_KEY,
+            "Need SIGNAL_RECIPIENTS to send notifs",
+            problems,
+        )
+        .map(|recipients| {
+            recipients
+                .split(',')
+                .map(|val| val.to_string())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+        let signal_sender = read_required_var(
+            SIGNAL_SENDER_KEY,
+            "Need SIGNAL_SENDER to send notifs",
+            problems,
+        );
+
+        config_builder
+            .signal_message(signal_message_prefix)
+            .signal_url(signal_url)
+            .signal_recipients(signal_recipients)
+            .signal_sender(signal_sender)
+            .signal_rate_limit(read_rate_limit_var(SIGNAL_RATE_LIMIT_KEY, problems))
+            .signal_quiet_hours(read_quiet_hours_var(SIGNAL_QUIET_HOURS_KEY, problems));
+    }
+
+    let schedule = read_var(SCHEDULE_KEY, problems);
+    if let Some(Err(e)) = schedule.as_deref().map(parse_schedule) {
+        problems.push(e);
+    }
+
+    let check_interval_s = parse_seconds_var(CHECK_INTERVAL_S_KEY, problems);
+    if check_interval_s == Some(0) {
+        problems.push(format!("{} must be more than 0", CHECK_INTERVAL_S_KEY));
+    }
+
+    config_builder
+        .schedule(schedule)
+        .check_interval_s(check_interval_s)
+        .jitter_s(parse_seconds_var(JITTER_S_KEY, problems))
+        .notification_types(notification_types);
+
+    // only the url and content type have no sensible stand in
+    config_builder.url(url?).content_type(content_type?);
+    let config = config_builder
+        .build()
+        .map_err(|e| problems.push(format!("Unable to build config: {}", e)))
+        .ok()?;
+
+    check_pagination(&config, problems);
+    check_transport(&config, problems);
+    check_login(&config, problems);
+    Some(config)
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    Html,
+    Text,
+    Regex,
+    XPath,
+    Json,
+    Csv,
+}
+
+impl TryFrom<&String> for ContentType {
+    type Error = &'static str;
+
+    fn try_from(value: &String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().trim() {
+            "html" => Ok(ContentType::Html),
+            "text" => Ok(ContentType::Text),
+            "regex" => Ok(ContentType::Regex),
+            "xpath" => Ok(ContentType::XPath),
+            "json" => Ok(ContentType::Json),
+            "csv" => Ok(ContentType::Csv),
+            _ => Err("Unknown content type"),
+        }
+    }
+}
+
+impl Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let content_type_string = match self {
+            ContentType::Html => "HTML",
+            ContentType::Text => "text",
+            ContentType::Regex => "regex",
+            ContentType::XPath => "XPath",
+            ContentType::Json => "JSON",
+            ContentType::Csv => "CSV",
+        };
+        f.write_fmt(format_args!("{content_type_string}"))
+    }
+}
+
+/// `Present` notifies when the matcher finds something, `Absent` when it finds nothing, e.g.
+/// when a "sold out" banner goes away.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Present,
+    #[serde(alias = "inverse")]
+    Absent,
+}
+
+impl TryFrom<&str> for MatchMode {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().trim() {
+            "present" => Ok(MatchMode::Present),
+            "absent" | "inverse" => Ok(MatchMode::Absent),
+            _ => Err("Unknown match mode"),
+        }
+    }
+}
+
+impl Display for MatchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let match_mode_string = match self {
+            MatchMode::Present => "present",
+            MatchMode::Absent => "absent",
+        };
+        f.write_fmt(format_args!("{}", match_mode_string))
+    }
+}
+
+/// `Every` notifies about all matches on every run, `Changes` only about matches that were added,
+/// removed or changed since the last notification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    #[default]
+    Every,
+    Changes,
+}
+
+impl TryFrom<&str> for NotifyOn {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().trim() {
+            "every" => Ok(NotifyOn::Every),
+            "changes" => Ok(NotifyOn::Changes),
+            _ => Err("Unknown notify on"),
+        }
+    }
+}
+
+impl Display for NotifyOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let notify_on_string = match self {
+            NotifyOn::Every => "every",
+            NotifyOn::Changes => "changes",
+        };
+        f.write_fmt(format_args!("{}", notify_on_string))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationType {
+    Signal,
+    Email,
+}
+
+impl TryFrom<&str> for NotificationType {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().trim() {
+            "signal" => Ok(NotificationType::Signal),
+            "email" => Ok(NotificationType::Email),
+            _ => Err("Unknown notification type"),
+        }
+    }
+}
+
+impl Display for NotificationType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let notification_type_string = match self {
+            NotificationType::Email => "Email",
+            NotificationType::Signal => "Signal",
+        };
+        f.write_fmt(format_args!("{}", notification_type_string))
+    }
+}
@@ -0,0 +1,284 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use cron::Schedule;
+use rand::Rng;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use crate::config::Config;
+use crate::error::{GemError, GemResult};
+use crate::fetch::TargetClient;
+use crate::state::{self, StateStore};
+use crate::{check_target, target_client, RunOptions};
+
+/// When a target should next be checked while running as a daemon.
+enum Timing {
+    Cron(Box<Schedule>),
+    Interval(Duration),
+}
+
+impl Timing {
+    fn from_config(config: &Config) -> GemResult<Timing> {
+        if let Some(expression) = &config.schedule {
+            let schedule = parse_schedule(expression)
+                .map_err(|e| GemError::Config(format!("{}: {}", config.label(), e)))?;
+            return Ok(Timing::Cron(Box::new(schedule)));
+        }
+
+        match config.check_interval_s {
+            Some(0) => Err(GemError::Config(format!(
+                "{}: check_interval_s must be more than 0",
+                config.label()
+            ))),
+            Some(seconds) => Ok(Timing::Interval(Duration::from_secs(seconds))),
+            None => Err(GemError::Config(format!(
+                "{}: Need a schedule or check_interval_s to run as a daemon",
+                config.label()
+            ))),
+        }
+    }
+
+    /// `None` once a cron schedule has no times left, e.g. one pinned to a past year.
+    fn next_delay(&self, is_first_run: bool) -> Option<Duration> {
+        match self {
+            Timing::Cron(schedule) => schedule
+                .upcoming(Utc)
+                .next()
+                // the time can pass while we work it out, which just means now
+                .map(|next| (next - Utc::now()).to_std().unwrap_or_default()),
+            Timing::Interval(_) if is_first_run => Some(Duration::ZERO),
+            Timing::Interval(interval) => Some(*interval),
+        }
+    }
+}
+
+/// Parses a cron expression, either the usual 5 field form or 6 fields with leading seconds.
+/// Weekdays are numbered as in crontab, 0 to 6 from Sunday with 7 also Sunday.
+pub fn parse_schedule(expression: &str) -> Result<Schedule, String> {
+    let mut fields = expression.split_whitespace().collect::<Vec<&str>>();
+    // the cron crate expects a leading seconds field
+    if fields.len() == 5 {
+        fields.insert(0, "0");
+    }
+    let weekday = fields.get(5).map(|weekday| cron_weekdays(weekday));
+    if let Some(weekday) = &weekday {
+        fields[5] = weekday;
+    }
+
+    let schedule = Schedule::from_str(&fields.join(" "))
+        .map_err(|e| format!("Invalid schedule '{}': {}", expression, e))?;
+    if schedule.upcoming(Utc).next().is_none() {
+        return Err(format!("Schedule '{}' has no upcoming times", expression));
+    }
+
+    Ok(schedule)
+}
+
+/// Renumbers a crontab weekday field for the cron crate, which counts 1 to 7 from Sunday.
+/// Numbered days are listed out as a range ending in Sunday as 7 can't be renumbered in place.
+fn cron_weekdays(field: &str) -> String {
+    field
+        .split(',')
+        .map(|part| {
+            let (range, step) = part.split_once('/').unwrap_or((part, "1"));
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, end),
+                // a lone start with a step runs to the end of the week
+                None if part.contains('/') => (range, "6"),
+                None => (range, range),
+            };
+
+            let days = match (
+                start.parse::<u32>(),
+                end.parse::<u32>(),
+                step.parse::<usize>(),
+            ) {
+                (Ok(start), Ok(end), Ok(step)) if start <= end && end <= 7 && step > 0 => {
+                    (start..=end).step_by(step)
+                }
+                // `*`, names and anything out of range are left for the cron crate
+                _ => return part.to_string(),
+            };
+            days.map(|day| (day % 7 + 1).to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Runs every target on its own schedule until SIGTERM or SIGINT is received, sharing one
+/// HTTP client between all of them for notifications. Each target fetches with its own client,
+/// built once here and kept for all of its checks.
+pub async fn run_daemon(configs: Vec<Config>, options: RunOptions) -> GemResult<()> {
+    // work out every schedule upfront so a bad one stops us before anything runs
+    let timings = configs
+        .iter()
+        .map(Timing::from_config)
+        .collect::<GemResult<Vec<Timing>>>()?;
+
+    let client = reqwest::Client::new();
+    let store = state::open_store()?;
+    // likewise a target whose client can't be built, e.g. a missing CA file
+    let targets = configs
+        .iter()
+        .map(|config| {
+            target_client(config, store.as_ref())
+                .map(Arc::new)
+                .map_err(|e| GemError::Config(format!("{}: {}", config.label(), e.message())))
+        })
+        .collect::<GemResult<Vec<Arc<TargetClient>>>>()?;
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+
+    let tasks = configs
+        .into_iter()
+        .zip(timings)
+        .zip(targets)
+        .map(|((config, timing), target)| {
+            tokio::spawn(schedule_target(
+                config,
+                timing,
+                target,
+                client.clone(),
+                store.clone(),
+                shutdown_receiver.clone(),
+                options,
+            ))
+        })
+        .collect::<Vec<tokio::task::JoinHandle<()>>>();
+
+    println!("Daemon started with {} target(s)", tasks.len());
+
+    wait_for_shutdown().await;
+    println!("Shutting down, waiting for running checks to finish");
+    shutdown_sender.send(true).ok();
+
+    for task in tasks {
+        task.await.ok();
+    }
+
+    Ok(())
+}
+
+async fn schedule_target(
+    config: Config,
+    timing: Timing,
+    target: Arc<TargetClient>,
+    client: reqwest::Client,
+    store: Arc<dyn StateStore>,
+    mut shutdown: watch::Receiver<bool>,
+    options: RunOptions,
+) {
+    let label = config.label();
+    let mut is_first_run = true;
+
+    loop {
+        let jitter = config
+            .jitter_s
+            .filter(|jitter_s| *jitter_s > 0)
+            .map_or(Duration::ZERO, |jitter_s| {
+                Duration::from_millis(rand::thread_rng().gen_range(0..jitter_s * 1000))
+            });
+        let Some(delay) = timing.next_delay(is_first_run) else {
+            eprintln!("{}: schedule has no upcoming times", label);
+            break;
+        };
+        let delay = delay + jitter;
+        is_first_run = false;
+
+        if options.is_debug {
+            println!("{}: next check in {}s", label, delay.as_secs());
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => break,
+        }
+
+        // run in its own task so a failing check doesn't stop the schedule
+        let run = tokio::spawn({
+            let config = config.clone();
+            let target = target.clone();
+            let client = client.clone();
+            let store = store.clone();
+            async move { check_target(&config, &target, &client, store.as_ref(), options).await }
+        });
+
+        match run.await {
+            Ok(Err(error)) => eprintln!("{}: {}", label, error),
+            Err(error) => eprintln!("{}: check failed: {}", label, error),
+            Ok(Ok(())) => {}
+        }
+
+        if *shutdown.borrow() {
+            break;
+        }
+    }
+
+    println!("{}: stopped", label);
+}
+
+async fn wait_for_shutdown() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(error) => {
+            eprintln!(
+                "Unable to listen for SIGTERM, only handling SIGINT: {}",
+                error
+            );
+            tokio::signal::ctrl_c().await.ok();
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => println!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => println!("Received SIGINT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weekdays(expression: &str) -> Vec<Weekday> {
+        let mut weekdays = parse_schedule(expression)
+            .unwrap()
+            .upcoming(Utc)
+            .take(14)
+            .map(|time| time.weekday())
+            .collect::<Vec<Weekday>>();
+        weekdays.sort_by_key(|weekday| weekday.num_days_from_sunday());
+        weekdays.dedup();
+        weekdays
+    }
+
+    #[test]
+    fn weekdays_count_from_sunday_as_0() {
+        use Weekday::*;
+        assert_eq!(weekdays("0 9 * * 1-5"), [Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(weekdays("0 9 * * 0"), [Sun]);
+        assert_eq!(weekdays("0 9 * * 7"), [Sun]);
+        assert_eq!(weekdays("0 9 * * 5-7"), [Sun, Fri, Sat]);
+        assert_eq!(weekdays("0 9 * * 0,6"), [Sun, Sat]);
+        assert_eq!(weekdays("0 9 * * 1-5/2"), [Mon, Wed, Fri]);
+        assert_eq!(weekdays("30 0 9 * * 3"), [Wed]);
+    }
+
+    #[test]
+    fn weekday_names_and_steps_are_kept() {
+        use Weekday::*;
+        assert_eq!(weekdays("0 9 * * Mon-Fri"), [Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(weekdays("0 9 * * */2"), [Sun, Tue, Thu, Sat]);
+        assert_eq!(weekdays("0 9 * * *").len(), 7);
+    }
+
+    #[test]
+    fn rejects_bad_weekdays() {
+        assert!(parse_schedule("0 9 * * 8").is_err());
+        assert!(parse_schedule("0 9 * * 5-1").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt::Display;
+
+/// Everything that can go wrong during a run, grouped by the stage that failed.
+///
+/// Each category maps to its own process exit code so wrappers can tell them apart:
+///
+/// | Category | Exit code |
+/// |----------|-----------|
+/// | Config   | 2         |
+/// | Fetch    | 3         |
+/// | Parse    | 4         |
+/// | State    | 5         |
+/// | Notify   | 6         |
+#[derive(Debug)]
+pub enum GemError {
+    /// Missing or malformed configuration
+    Config(String),
+    /// The target couldn't be downloaded
+    Fetch(String),
+    /// The downloaded content couldn't be searched
+    Parse(String),
+    /// The notification state on disk couldn't be read or written
+    State(String),
+    /// A notification couldn't be built or sent
+    Notify(String),
+}
+
+pub type GemResult<T> = Result<T, GemError>;
+
+impl GemError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GemError::Config(_) => 2,
+            GemError::Fetch(_) => 3,
+            GemError::Parse(_) => 4,
+            GemError::State(_) => 5,
+            GemError::Notify(_) => 6,
+        }
+    }
+
+    /// The message without the category
+    pub fn message(&self) -> &str {
+        match self {
+            GemError::Config(message)
+            | GemError::Fetch(message)
+            | GemError::Parse(message)
+            | GemError::State(message)
+            | GemError::Notify(message) => message,
+        }
+    }
+}
+
+impl Display for GemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let category = match self {
+            GemError::Config(_) => "config",
+            GemError::Fetch(_) => "fetch",
+            GemError::Parse(_) => "parse",
+            GemError::State(_) => "state",
+            GemError::Notify(_) => "notify",
+        };
+        f.write_fmt(format_args!("{} error: {}", category, self.message()))
+    }
+}
+
+impl std::error::Error for GemError {}
@@ -0,0 +1,897 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::config::*;
+use crate::error::{GemError, GemResult};
+
+const DEFAULT_INTERVAL_S: u64 = 60 * 5;
+const SECRETS_DIR: &str = "/run/secrets";
+/// Where Kubernetes mounts a config file that holds no credentials
+const CONFIG_DIR: &str = "/etc/gem";
+/// Where the container formats keep state, on a volume so it outlives each run
+const STATE_DIR: &str = "/var/lib/gem/";
+/// Switches for trying gem out by hand, a deployment shouldn't inherit them
+const LOCAL_KEYS: &[&str] = &[DEBUG_KEY, PREVENT_EMAIL_KEY, PREVENT_MESSAGE_KEY];
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// Labels for an Ofelia job-run
+    Ofelia,
+    /// A docker-compose service running `gem daemon`
+    Compose,
+    /// A Kubernetes CronJob with a Secret
+    Kubernetes,
+    /// A systemd service and timer pair
+    Systemd,
+}
+
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub name: String,
+    pub image: String,
+    /// Where to write a file per secret for the formats that mount them
+    pub secrets_dir: Option<String>,
+}
+
+/// A file the deployment has to provide for gem to read.
+struct SecretFile {
+    name: String,
+    contents: String,
+}
+
+/// Everything gem would read right now, with `*_FILE` values resolved and the config file
+/// checked the same way `gem run` would.
+struct Deployment {
+    plain: Vec<(String, String)>,
+    secrets: Vec<(String, String)>,
+    /// Env vars naming a file gem reads, e.g. `CA_CERT`, with the file to mount in its place
+    file_vars: Vec<(String, SecretFile)>,
+    /// The config file's targets, with the cadence each one runs at
+    targets: Vec<(toml::Table, Cadence)>,
+    /// Files the config file's targets read, e.g. `client_key`, by the target reading them
+    target_files: Vec<(usize, SecretFile)>,
+    /// Whether the config file holds credentials itself, e.g. `headers` or the Signal settings
+    is_config_secret: bool,
+    /// Cadence of the target built from env, when there's no config file
+    env_cadence: Cadence,
+}
+
+/// One scheduled `gem run`, covering every target that shares its cadence.
+struct Job {
+    name: String,
+    cadence: Cadence,
+    /// Indexes into `Deployment::targets`, empty for a target built from env
+    targets: Vec<usize>,
+}
+
+impl Deployment {
+    fn load(config_file: &Option<String>) -> GemResult<Deployment> {
+        let mut plain = Vec::new();
+        let mut secrets = Vec::new();
+        let mut file_vars = Vec::new();
+
+        for key in ENV_KEYS {
+            // each job points at its own copy of the config
+            if *key == CONFIG_FILE_KEY || LOCAL_KEYS.contains(key) {
+                continue;
+            }
+
+            let Some(value) = env_var(key)? else {
+                continue;
+            };
+
+            if SECRET_KEYS.contains(key) {
+                secrets.push((key.to_string(), value));
+            } else if FILE_KEYS.contains(key) && config_file.is_none() {
+                let file = read_secret(&secret_name(key), &value)?;
+                file_vars.push((key.to_string(), file));
+            } else {
+                plain.push((key.to_string(), value));
+            }
+        }
+
+        let env_cadence = {
+            let schedule = plain
+                .iter()
+                .find(|(key, _)| key == SCHEDULE_KEY)
+                .map(|(_, schedule)| schedule.as_str());
+            let check_interval_s = plain
+                .iter()
+                .find(|(key, _)| key == CHECK_INTERVAL_S_KEY)
+                .and_then(|(_, seconds)| seconds.parse::<u64>().ok());
+            Cadence::new(schedule, check_interval_s)
+        };
+
+        let mut targets = Vec::new();
+        let mut target_files = Vec::new();
+        let mut is_config_secret = false;
+        if let Some(config_file) = config_file {
+            for (index, (config, table)) in load_config_tables(config_file)?.into_iter().enumerate()
+            {
+                is_config_secret |= SECRET_TARGET_KEYS
+                    .iter()
+                    .any(|key| table.contains_key(*key));
+
+                for key in FILE_TARGET_KEYS {
+                    if let Some(path) = table.get(*key).and_then(|value| value.as_str()) {
+                        let file = read_secret(&target_file_name(index, key), path)?;
+                        target_files.push((index, file));
+                    }
+                }
+
+                let cadence = Cadence::new(config.schedule.as_deref(), config.check_interval_s);
+                targets.push((table, cadence));
+            }
+        }
+
+        Ok(Deployment {
+            plain,
+            secrets,
+            file_vars,
+            targets,
+            target_files,
+            is_config_secret,
+            env_cadence,
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.plain
+            .iter()
+            .find(|(plain_key, _)| plain_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The env a container gets, with state moved onto its volume as the host's paths won't be
+    /// there.
+    fn container_env(&self) -> Vec<(String, String)> {
+        let mut env = self
+            .plain
+            .iter()
+            .filter(|(key, _)| key != NOTIFICATION_WRITE_DIR_KEY && key != STATE_DB_KEY)
+            .cloned()
+            .collect::<Vec<(String, String)>>();
+        env.push((
+            NOTIFICATION_WRITE_DIR_KEY.to_string(),
+            STATE_DIR.to_string(),
+        ));
+        env
+    }
+
+    /// Targets that run at the same cadence share a job, so a config file where every target
+    /// runs at the same cadence is a single job like one built from env.
+    fn jobs(&self, name: &str) -> Vec<Job> {
+        if self.targets.is_empty() {
+            return vec![Job {
+                name: name.to_string(),
+                cadence: self.env_cadence.clone(),
+                targets: Vec::new(),
+            }];
+        }
+
+        let mut jobs: Vec<Job> = Vec::new();
+        for (index, (_, cadence)) in self.targets.iter().enumerate() {
+            match jobs.iter_mut().find(|job| job.cadence == *cadence) {
+                Some(job) => job.targets.push(index),
+                None => jobs.push(Job {
+                    name: format!("{}-{}", name, jobs.len() + 1),
+                    cadence: cadence.clone(),
+                    targets: vec![index],
+                }),
+            }
+        }
+
+        if let [job] = &mut jobs[..] {
+            job.name = name.to_string();
+        }
+        jobs
+    }
+
+    /// The config file for a job's targets, with the files they read pointed at `mount_dir`.
+    fn job_config(&self, job: &Job, mount_dir: &str) -> Option<SecretFile> {
+        if job.targets.is_empty() {
+            return None;
+        }
+
+        let targets = job
+            .targets
+            .iter()
+            .map(|index| {
+                let mut table = self.targets[*index].0.clone();
+                for key in FILE_TARGET_KEYS {
+                    if table.contains_key(*key) {
+                        let path = format!("{}/{}", mount_dir, target_file_name(*index, key));
+                        table.insert(key.to_string(), toml::Value::String(path));
+                    }
+                }
+                toml::Value::Table(table)
+            })
+            .collect::<Vec<toml::Value>>();
+
+        let mut config = toml::Table::new();
+        config.insert("target".to_string(), toml::Value::Array(targets));
+        Some(SecretFile {
+            name: format!("{}.toml", job.name),
+            contents: toml::to_string(&config).unwrap_or_default(),
+        })
+    }
+
+    /// Files gem reads by path, from env and from the config file's targets.
+    fn mounted_files(&self) -> impl Iterator<Item = &SecretFile> {
+        self.file_vars
+            .iter()
+            .map(|(_, file)| file)
+            .chain(self.target_files.iter().map(|(_, file)| file))
+    }
+
+    /// Every file the deployment mounts, with the env secrets and job configs.
+    fn files<'a>(&'a self, configs: &'a [SecretFile]) -> Vec<(String, &'a str)> {
+        let mut files = self
+            .secrets
+            .iter()
+            .map(|(key, value)| (secret_name(key), value.as_str()))
+            .collect::<Vec<(String, &str)>>();
+        files.extend(
+            self.mounted_files()
+                .chain(configs)
+                .map(|file| (file.name.clone(), file.contents.as_str())),
+        );
+        files
+    }
+}
+
+/// A file a target reads, `key` in its config or `<key>_FILE` in env.
+fn read_secret(name: &str, path: &str) -> GemResult<SecretFile> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| GemError::Config(format!("Unable to read {}: {}", path, e)))?;
+    Ok(SecretFile {
+        name: name.to_string(),
+        contents,
+    })
+}
+
+fn target_file_name(index: usize, key: &str) -> String {
+    format!("target{}_{}", index + 1, key)
+}
+
+/// How often the scheduler should start a run.
+#[derive(Clone, PartialEq)]
+enum Cadence {
+    /// 5 field cron expression
+    Cron(String),
+    Every(u64),
+}
+
+impl Cadence {
+    fn new(schedule: Option<&str>, check_interval_s: Option<u64>) -> Cadence {
+        if let Some(schedule) = schedule {
+            let fields = schedule.split_whitespace().collect::<Vec<&str>>();
+            // drop the seconds field from 6 field expressions, schedulers here work in minutes
+            let fields = if fields.len() == 6 {
+                &fields[1..]
+            } else {
+                &fields[..]
+            };
+            return Cadence::Cron(fields.join(" "));
+        }
+
+        Cadence::Every(check_interval_s.unwrap_or(DEFAULT_INTERVAL_S))
+    }
+
+    fn to_ofelia(&self) -> String {
+        match self {
+            Cadence::Cron(expression) => format!("0 {}", expression),
+            Cadence::Every(seconds) => format!("@every {}s", seconds),
+        }
+    }
+
+    /// Cron only repeats evenly within an hour or a day, so an interval that doesn't divide
+    /// into one of those can't be written as an expression.
+    fn to_cron(&self) -> GemResult<String> {
+        let seconds = match self {
+            Cadence::Cron(expression) => return Ok(expression.clone()),
+            Cadence::Every(seconds) => *seconds,
+        };
+
+        let (minutes, hours) = (seconds / 60, seconds / 60 / 60);
+        if seconds % 60 == 0 && (1..60).contains(&minutes) && 60 % minutes == 0 {
+            return Ok(format!("*/{} * * * *", minutes));
+        }
+        if seconds % (60 * 60) == 0 && (1..24).contains(&hours) && 24 % hours == 0 {
+            return Ok(format!("0 */{} * * *", hours));
+        }
+        if seconds == 60 * 60 * 24 {
+            return Ok("0 0 * * *".to_string());
+        }
+
+        Err(GemError::Config(format!(
+            "Unable to repeat every {}s in a cron schedule, set a schedule instead or a \
+             check_interval_s that divides evenly into an hour or a day",
+            seconds
+        )))
+    }
+
+    fn to_systemd_timer(&self) -> String {
+        match self {
+            Cadence::Cron(expression) => {
+                format!("OnCalendar={}", cron_to_on_calendar(expression))
+            }
+            Cadence::Every(seconds) => {
+                format!("OnBootSec={}s\nOnUnitActiveSec={}s", seconds, seconds)
+            }
+        }
+    }
+}
+
+/// Builds the deployment artifact for the current config, which has to pass `gem check-config`
+/// first.
+pub fn export(config_file: &Option<String>, options: &ExportOptions) -> GemResult<String> {
+    let deployment = Deployment::load(config_file)?;
+    let jobs = deployment.jobs(&options.name);
+    let configs = configs(&deployment, &jobs, options);
+
+    let output = match options.format {
+        ExportFormat::Ofelia => export_ofelia(&deployment, &jobs, &configs, options),
+        ExportFormat::Compose => export_compose(&deployment, &configs, options),
+        ExportFormat::Kubernetes => export_kubernetes(&deployment, &jobs, &configs, options)?,
+        ExportFormat::Systemd => export_systemd(&deployment, &jobs, &configs, options),
+    };
+
+    if let Some(secrets_dir) = &options.secrets_dir {
+        write_secrets(&deployment.files(&configs), secrets_dir)?;
+    }
+
+    Ok(output)
+}
+
+/// The config file each job is given, compose has a single one as the daemon runs every target.
+fn configs(deployment: &Deployment, jobs: &[Job], options: &ExportOptions) -> Vec<SecretFile> {
+    match options.format {
+        ExportFormat::Compose => {
+            let job = Job {
+                name: options.name.clone(),
+                cadence: deployment.env_cadence.clone(),
+                targets: (0..deployment.targets.len()).collect(),
+            };
+            deployment
+                .job_config(&job, SECRETS_DIR)
+                .into_iter()
+                .collect()
+        }
+        ExportFormat::Systemd => jobs
+            .iter()
+            .filter_map(|job| deployment.job_config(job, &credentials_dir(&job.name)))
+            .collect(),
+        ExportFormat::Ofelia | ExportFormat::Kubernetes => jobs
+            .iter()
+            .filter_map(|job| deployment.job_config(job, SECRETS_DIR))
+            .collect(),
+    }
+}
+
+fn write_secrets(files: &[(String, &str)], secrets_dir: &str) -> GemResult<()> {
+    fs::create_dir_all(secrets_dir)
+        .map_err(|e| GemError::State(format!("Unable to create {}: {}", secrets_dir, e)))?;
+
+    for (name, contents) in files {
+        let path = Path::new(secrets_dir).join(name);
+        write_private(&path, contents)
+            .map_err(|e| GemError::State(format!("Unable to write {}: {}", path.display(), e)))?;
+        eprintln!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Writes a file only the owner can read, without a moment where it's readable by others. An
+/// existing file is emptied and narrowed to the owner before anything is written.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // the mode only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+fn secret_name(key: &str) -> String {
+    key.to_lowercase()
+}
+
+/// JSON strings are valid double quoted YAML scalars
+fn yaml_quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// The files to create before deploying, `--secrets-dir` writes them all.
+fn files_comment(files: &[(String, &str)], secrets_dir: &str) -> String {
+    let mut output = String::new();
+    if !files.is_empty() {
+        output.push_str(&format!(
+            "# Create a file for each secret in {}, or write them with --secrets-dir:\n",
+            secrets_dir
+        ));
+        for (name, _) in files {
+            output.push_str(&format!("#   {}/{}\n", secrets_dir, name));
+        }
+    }
+    output
+}
+
+/// Env vars pointing gem at the files mounted in `mount_dir`.
+fn file_env(deployment: &Deployment, mount_dir: &str) -> Vec<(String, String)> {
+    deployment
+        .secrets
+        .iter()
+        .map(|(key, _)| {
+            (
+                format!("{}{}", key, FILE_SUFFIX),
+                format!("{}/{}", mount_dir, secret_name(key)),
+            )
+        })
+        .chain(
+            deployment
+                .file_vars
+                .iter()
+                .map(|(key, file)| (key.clone(), format!("{}/{}", mount_dir, file.name))),
+        )
+        .collect()
+}
+
+fn export_ofelia(
+    deployment: &Deployment,
+    jobs: &[Job],
+    configs: &[SecretFile],
+    options: &ExportOptions,
+) -> String {
+    let files = deployment.files(configs);
+    let mut output = files_comment(&files, "./secrets");
+
+    // Ofelia has no secret store so point the *_FILE keys at files mounted alongside
+    let environment = deployment
+        .container_env()
+        .into_iter()
+        .chain(file_env(deployment, SECRETS_DIR))
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>();
+
+    // Ofelia hands the volumes straight to docker so they need an absolute path
+    let mut volumes = vec![format!(
+        "{}-state:{}",
+        options.name,
+        STATE_DIR.trim_end_matches('/')
+    )];
+    if !files.is_empty() {
+        volumes.push(format!("${{PWD}}/secrets:{}:ro", SECRETS_DIR));
+    }
+
+    output.push_str("labels:\n");
+    output.push_str("  ofelia.enabled: \"true\"\n");
+    for job in jobs {
+        let label = format!("ofelia.job-run.{}", job.name);
+        let mut environment = environment.clone();
+        if !job.targets.is_empty() {
+            environment.push(format!(
+                "{}={}/{}.toml",
+                CONFIG_FILE_KEY, SECRETS_DIR, job.name
+            ));
+        }
+
+        output.push_str(&format!(
+            "  {}.schedule: {}\n",
+            label,
+            yaml_quote(&job.cadence.to_ofelia())
+        ));
+        output.push_str(&format!(
+            "  {}.image: {}\n",
+            label,
+            yaml_quote(&options.image)
+        ));
+        output.push_str(&format!("  {}.command: \"gem run\"\n", label));
+        output.push_str(&format!(
+            "  {}.environment: {}\n",
+            label,
+            yaml_quote(&serde_json::to_string(&environment).unwrap_or_default())
+        ));
+        output.push_str(&format!(
+            "  {}.volume: {}\n",
+            label,
+            yaml_quote(&serde_json::to_string(&volumes).unwrap_or_default())
+        ));
+    }
+
+    output
+}
+
+fn export_compose(
+    deployment: &Deployment,
+    configs: &[SecretFile],
+    options: &ExportOptions,
+) -> String {
+    let files = deployment.files(configs);
+    let mut output = files_comment(&files, "./secrets");
+
+    output.push_str("services:\n");
+    output.push_str(&format!("  {}:\n", options.name));
+    output.push_str(&format!("    image: {}\n", yaml_quote(&options.image)));
+    output.push_str("    command: [\"gem\", \"daemon\"]\n");
+    output.push_str("    restart: unless-stopped\n");
+    output.push_str("    environment:\n");
+    for (key, value) in deployment.container_env() {
+        output.push_str(&format!("      {}: {}\n", key, yaml_quote(&value)));
+    }
+    // the daemon needs a schedule, so carry over the default the other formats use
+    if let Cadence::Every(seconds) = deployment.env_cadence {
+        if deployment.get(CHECK_INTERVAL_S_KEY).is_none() && deployment.targets.is_empty() {
+            output.push_str(&format!(
+                "      {}: {}\n",
+                CHECK_INTERVAL_S_KEY,
+                yaml_quote(&seconds.to_string())
+            ));
+        }
+    }
+    for (key, value) in file_env(deployment, SECRETS_DIR) {
+        output.push_str(&format!("      {}: {}\n", key, yaml_quote(&value)));
+    }
+    for config in configs {
+        output.push_str(&format!(
+            "      {}: {}\n",
+            CONFIG_FILE_KEY,
+            yaml_quote(&format!("{}/{}", SECRETS_DIR, config.name))
+        ));
+    }
+    output.push_str("    volumes:\n");
+    output.push_str(&format!(
+        "      - state:{}\n",
+        STATE_DIR.trim_end_matches('/')
+    ));
+
+    if !files.is_empty() {
+        output.push_str("    secrets:\n");
+        for (name, _) in &files {
+            output.push_str(&format!("      - {}\n", yaml_quote(name)));
+        }
+
+        output.push_str("secrets:\n");
+        for (name, _) in &files {
+            output.push_str(&format!("  {}:\n", yaml_quote(name)));
+            output.push_str(&format!("    file: ./secrets/{}\n", name));
+        }
+    }
+
+    output.push_str("volumes:\n");
+    output.push_str("  state:\n");
+
+    output
+}
+
+fn export_kubernetes(
+    deployment: &Deployment,
+    jobs: &[Job],
+    configs: &[SecretFile],
+    options: &ExportOptions,
+) -> GemResult<String> {
+    let secret = format!("{}-secrets", options.name);
+    let config_map = format!("{}-config", options.name);
+    let state = format!("{}-state", options.name);
+    // a config without credentials goes in a ConfigMap instead of the Secret
+    let (secret_configs, plain_configs) = match deployment.is_config_secret {
+        true => (configs, &[][..]),
+        false => (&[][..], configs),
+    };
+    let config_dir = match deployment.is_config_secret {
+        true => SECRETS_DIR,
+        false => CONFIG_DIR,
+    };
+    let files = deployment
+        .mounted_files()
+        .chain(secret_configs)
+        .collect::<Vec<&SecretFile>>();
+    let mut output = String::new();
+
+    if !deployment.secrets.is_empty() || !files.is_empty() {
+        output.push_str("apiVersion: v1\n");
+        output.push_str("kind: Secret\n");
+        output.push_str("metadata:\n");
+        output.push_str(&format!("  name: {}\n", secret));
+        output.push_str("type: Opaque\n");
+        output.push_str("stringData:\n");
+        for (key, value) in &deployment.secrets {
+            output.push_str(&format!("  {}: {}\n", key, yaml_quote(value)));
+        }
+        for file in &files {
+            output.push_str(&format!(
+                "  {}: {}\n",
+                yaml_quote(&file.name),
+                yaml_quote(&file.contents)
+            ));
+        }
+        output.push_str("---\n");
+    }
+
+    if !plain_configs.is_empty() {
+        output.push_str("apiVersion: v1\n");
+        output.push_str("kind: ConfigMap\n");
+        output.push_str("metadata:\n");
+        output.push_str(&format!("  name: {}\n", config_map));
+        output.push_str("data:\n");
+        for config in plain_configs {
+            output.push_str(&format!(
+                "  {}: {}\n",
+                yaml_quote(&config.name),
+                yaml_quote(&config.contents)
+            ));
+        }
+        output.push_str("---\n");
+    }
+
+    output.push_str("apiVersion: v1\n");
+    output.push_str("kind: PersistentVolumeClaim\n");
+    output.push_str("metadata:\n");
+    output.push_str(&format!("  name: {}\n", state));
+    output.push_str("spec:\n");
+    output.push_str("  accessModes: [\"ReadWriteOnce\"]\n");
+    output.push_str("  resources:\n");
+    output.push_str("    requests:\n");
+    output.push_str("      storage: 1Gi\n");
+
+    for job in jobs {
+        output.push_str("---\n");
+        output.push_str("apiVersion: batch/v1\n");
+        output.push_str("kind: CronJob\n");
+        output.push_str("metadata:\n");
+        output.push_str(&format!("  name: {}\n", job.name));
+        output.push_str("spec:\n");
+        output.push_str(&format!(
+            "  schedule: {}\n",
+            yaml_quote(&job.cadence.to_cron()?)
+        ));
+        output.push_str("  concurrencyPolicy: Forbid\n");
+        output.push_str("  jobTemplate:\n");
+        output.push_str("    spec:\n");
+        output.push_str("      template:\n");
+        output.push_str("        spec:\n");
+        output.push_str("          restartPolicy: Never\n");
+        output.push_str("          containers:\n");
+        output.push_str(&format!("            - name: {}\n", job.name));
+        output.push_str(&format!(
+            "              image: {}\n",
+            yaml_quote(&options.image)
+        ));
+        output.push_str("              args: [\"gem\", \"run\"]\n");
+        output.push_str("              env:\n");
+        let mut env = deployment.container_env();
+        env.extend(
+            deployment
+                .file_vars
+                .iter()
+                .map(|(key, file)| (key.clone(), format!("{}/{}", SECRETS_DIR, file.name))),
+        );
+        if !job.targets.is_empty() {
+            env.push((
+                CONFIG_FILE_KEY.to_string(),
+                format!("{}/{}.toml", config_dir, job.name),
+            ));
+        }
+        for (key, value) in env {
+            output.push_str(&format!("                - name: {}\n", key));
+            output.push_str(&format!(
+                "                  value: {}\n",
+                yaml_quote(&value)
+            ));
+        }
+        for (key, _) in &deployment.secrets {
+            output.push_str(&format!("                - name: {}\n", key));
+            output.push_str("                  valueFrom:\n");
+            output.push_str("                    secretKeyRef:\n");
+            output.push_str(&format!("                      name: {}\n", secret));
+            output.push_str(&format!("                      key: {}\n", key));
+        }
+
+        output.push_str("              volumeMounts:\n");
+        output.push_str("                - name: state\n");
+        output.push_str(&format!(
+            "                  mountPath: {}\n",
+            STATE_DIR.trim_end_matches('/')
+        ));
+        if !files.is_empty() {
+            output.push_str("                - name: secrets\n");
+            output.push_str(&format!("                  mountPath: {}\n", SECRETS_DIR));
+            output.push_str("                  readOnly: true\n");
+        }
+        if !plain_configs.is_empty() {
+            output.push_str("                - name: config\n");
+            output.push_str(&format!("                  mountPath: {}\n", CONFIG_DIR));
+            output.push_str("                  readOnly: true\n");
+        }
+
+        output.push_str("          volumes:\n");
+        output.push_str("            - name: state\n");
+        output.push_str("              persistentVolumeClaim:\n");
+        output.push_str(&format!("                claimName: {}\n", state));
+        if !files.is_empty() {
+            output.push_str("            - name: secrets\n");
+            output.push_str("              secret:\n");
+            output.push_str(&format!("                secretName: {}\n", secret));
+            output.push_str("                items:\n");
+            for file in &files {
+                output.push_str(&format!(
+                    "                  - key: {}\n",
+                    yaml_quote(&file.name)
+                ));
+                output.push_str(&format!(
+                    "                    path: {}\n",
+                    yaml_quote(&file.name)
+                ));
+            }
+        }
+        if !plain_configs.is_empty() {
+            output.push_str("            - name: config\n");
+            output.push_str("              configMap:\n");
+            output.push_str(&format!("                name: {}\n", config_map));
+        }
+    }
+
+    Ok(output)
+}
+
+/// Where systemd puts the credentials the unit loads, `%d` in the unit itself.
+fn credentials_dir(unit: &str) -> String {
+    format!("/run/credentials/{}.service", unit)
+}
+
+fn export_systemd(
+    deployment: &Deployment,
+    jobs: &[Job],
+    configs: &[SecretFile],
+    options: &ExportOptions,
+) -> String {
+    let source_dir = format!("/etc/{}", options.name);
+    let mut output = files_comment(&deployment.files(configs), &source_dir);
+
+    for (index, job) in jobs.iter().enumerate() {
+        if index > 0 {
+            output.push('\n');
+        }
+
+        output.push_str(&format!("# {}.service\n", job.name));
+        output.push_str("[Unit]\n");
+        output.push_str("Description=gem, look for things in places\n");
+        output.push_str("Wants=network-online.target\n");
+        output.push_str("After=network-online.target\n");
+        output.push('\n');
+        output.push_str("[Service]\n");
+        output.push_str("Type=oneshot\n");
+        output.push_str("ExecStart=/usr/local/bin/gem run\n");
+        output.push_str("DynamicUser=yes\n");
+        output.push_str(&format!("StateDirectory={}\n", options.name));
+        for (key, value) in &deployment.plain {
+            output.push_str(&format!(
+                "Environment={}\n",
+                systemd_quote(&format!("{}={}", key, value))
+            ));
+        }
+        if deployment.get(NOTIFICATION_WRITE_DIR_KEY).is_none() {
+            output.push_str(&format!(
+                "Environment={}=%S/{}/\n",
+                NOTIFICATION_WRITE_DIR_KEY, options.name
+            ));
+        }
+
+        // systemd credentials are only readable by the service and exposed under %d
+        let mut credentials = file_env(deployment, "%d");
+        let config = configs
+            .iter()
+            .find(|config| config.name == format!("{}.toml", job.name));
+        if let Some(config) = config {
+            credentials.push((CONFIG_FILE_KEY.to_string(), format!("%d/{}", config.name)));
+        }
+        let names = deployment
+            .secrets
+            .iter()
+            .map(|(key, _)| secret_name(key))
+            .chain(
+                deployment
+                    .file_vars
+                    .iter()
+                    .map(|(_, file)| file.name.clone()),
+            )
+            .chain(
+                deployment
+                    .target_files
+                    .iter()
+                    .filter(|(target, _)| job.targets.contains(target))
+                    .map(|(_, file)| file.name.clone()),
+            )
+            .chain(config.map(|config| config.name.clone()));
+        for name in names {
+            output.push_str(&format!(
+                "LoadCredential={}:{}/{}\n",
+                name, source_dir, name
+            ));
+        }
+        for (key, value) in credentials {
+            output.push_str(&format!("Environment={}={}\n", key, value));
+        }
+
+        output.push('\n');
+        output.push_str(&format!("# {}.timer\n", job.name));
+        output.push_str("[Unit]\n");
+        output.push_str(&format!("Description=Run {} on a schedule\n", job.name));
+        output.push('\n');
+        output.push_str("[Timer]\n");
+        output.push_str(&job.cadence.to_systemd_timer());
+        output.push('\n');
+        output.push_str("Persistent=true\n");
+        output.push('\n');
+        output.push_str("[Install]\n");
+        output.push_str("WantedBy=timers.target\n");
+    }
+
+    output
+}
+
+fn systemd_quote(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+    )
+}
+
+/// Converts a 5 field cron expression into a systemd `OnCalendar` value.
+fn cron_to_on_calendar(expression: &str) -> String {
+    let fields = expression.split_whitespace().collect::<Vec<&str>>();
+    let [minute, hour, day, month, weekday] = fields[..] else {
+        return expression.to_string();
+    };
+
+    let calendar_field = |field: &str, start: &str| {
+        field
+            .replace("*/", &format!("{}/", start))
+            .replace('-', "..")
+    };
+
+    let date = format!(
+        "*-{}-{}",
+        calendar_field(month, "1"),
+        calendar_field(day, "1")
+    );
+    let time = format!(
+        "{}:{}:00",
+        calendar_field(hour, "0"),
+        calendar_field(minute, "0")
+    );
+
+    if weekday == "*" {
+        return format!("{} {}", date, time);
+    }
+
+    const WEEKDAYS: [&str; 8] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    let weekday = weekday
+        .split(',')
+        .map(|part| {
+            part.split('-')
+                .map(|day| {
+                    day.parse::<usize>()
+                        .ok()
+                        .and_then(|index| WEEKDAYS.get(index))
+                        .map_or(day.to_string(), |name| name.to_string())
+                })
+                .collect::<Vec<String>>()
+                .join("..")
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+
+    format!("{} {} {}", weekday, date, time)
+}
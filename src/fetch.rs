@@ -0,0 +1,529 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::{
+    Certificate, Client, Identity, Method, Proxy, RequestBuilder, Response, StatusCode, Url,
+};
+use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
+use scraper::{Html, Selector};
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::error::{GemError, GemResult};
+use crate::hosts;
+use crate::state::PageCache;
+
+/// Pretending to be google bot helps make sure we get a server-side rendered version of the app
+pub const GOOGLEBOT_USER_AGENT: &str = "Mozilla/5.0 AppleWebKit/537.36 (KHTML, like Gecko; compatible; Googlebot/2.1; +http://www.google.com/bot.html) Chrome/W.X.Y.Z Safari/537.36";
+
+const DEFAULT_CONNECT_TIMEOUT_S: u64 = 10;
+const DEFAULT_TIMEOUT_S: u64 = 30;
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_RETRY_DELAY_S: u64 = 1;
+const DEFAULT_RETRY_STATUSES: &[u16] = &[429, 500, 502, 503, 504];
+/// Longest wait between attempts, whether from backoff or a `Retry-After`
+const MAX_RETRY_DELAY_S: u64 = 5 * 60;
+const DEFAULT_MAX_PAGES: u32 = 10;
+/// Replaced with the page number in `page_urls`
+pub const PAGE_PLACEHOLDER: &str = "{page}";
+
+/// A downloaded page along with the validators to send next time.
+pub struct Page {
+    pub url: Url,
+    pub body: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// How fetching a page went once any retries are over.
+enum Download {
+    Page(Page),
+    NotModified,
+    /// The server answered with an error status that isn't worth retrying
+    Refused(StatusCode),
+}
+
+/// How one attempt at fetching went.
+enum Attempt {
+    Fetched(Page),
+    NotModified,
+    Refused(StatusCode),
+    /// Worth trying again, after at least `retry_after` if the server asked for it
+    Failed {
+        error: GemError,
+        retry_after: Option<Duration>,
+    },
+}
+
+/// A target's client and cookie jar, built once and kept for every check so cookies carry over
+/// from one check to the next.
+pub struct TargetClient {
+    pub client: Client,
+    jar: Arc<CookieStoreMutex>,
+    /// Whether the jar holds a session worth trying before logging in
+    has_session: AtomicBool,
+}
+
+impl TargetClient {
+    /// `saved` is the session an earlier run left in the state store, if any.
+    pub fn new(config: &Config, saved: Option<&str>) -> GemResult<TargetClient> {
+        let jar = Arc::new(CookieStoreMutex::new(cookie_store(config, saved)?));
+        Ok(TargetClient {
+            client: build_client(config, jar.clone())?,
+            jar,
+            has_session: AtomicBool::new(saved.is_some()),
+        })
+    }
+
+    pub fn has_session(&self) -> bool {
+        self.has_session.load(Ordering::Relaxed)
+    }
+
+    /// Empties the jar of everything but the target's `cookies`, ready to log in afresh.
+    pub fn clear_session(&self, config: &Config) -> GemResult<()> {
+        let mut cookies = self
+            .jar
+            .lock()
+            .map_err(|_| GemError::State("Cookie jar lock poisoned".to_string()))?;
+        *cookies = cookie_store(config, None)?;
+        self.has_session.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Every cookie in the jar, session ones included, to be passed to `new` next run.
+    pub fn save_session(&self) -> GemResult<String> {
+        let cookies = self
+            .jar
+            .lock()
+            .map_err(|_| GemError::State("Cookie jar lock poisoned".to_string()))?;
+
+        let mut saved = Vec::new();
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(&cookies, &mut saved)
+            .map_err(|e| GemError::State(format!("Unable to serialise cookies: {}", e)))?;
+        let saved = String::from_utf8(saved)
+            .map_err(|e| GemError::State(format!("Unable to serialise cookies: {}", e)))?;
+
+        self.has_session.store(true, Ordering::Relaxed);
+        Ok(saved)
+    }
+}
+
+/// The target's cookies, picking up a session saved by an earlier run and seeded from `cookies`.
+fn cookie_store(config: &Config, saved: Option<&str>) -> GemResult<CookieStore> {
+    let mut cookies = match saved {
+        Some(saved) => cookie_store::serde::json::load(saved.as_bytes()).unwrap_or_else(|e| {
+            // it's replaced once logged in again
+            eprintln!(
+                "{}: ignoring saved session, unable to parse it: {}",
+                config.label(),
+                e
+            );
+            CookieStore::default()
+        }),
+        None => CookieStore::default(),
+    };
+
+    for (name, value) in &config.cookies {
+        cookies
+            .parse(&format!("{}={}", name, value), &config.url)
+            .map_err(|e| GemError::Config(format!("Invalid cookie {}: {}", name, e)))?;
+    }
+
+    Ok(cookies)
+}
+
+/// Builds the client used to fetch a target, with its own cookie jar and the target's proxy and TLS
+/// settings.
+fn build_client(config: &Config, jar: Arc<CookieStoreMutex>) -> GemResult<Client> {
+    let mut builder = Client::builder();
+
+    if let Some(proxy) = &config.proxy {
+        let proxy =
+            Proxy::all(proxy).map_err(|e| GemError::Config(format!("Invalid proxy: {}", e)))?;
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(ca_cert) = &config.ca_cert {
+        let certificates = Certificate::from_pem_bundle(&read_pem(ca_cert)?)
+            .map_err(|e| GemError::Config(format!("Invalid CA certificate {}: {}", ca_cert, e)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&config.client_cert, &config.client_key) {
+        (Some(client_cert), Some(client_key)) => {
+            let identity = Identity::from_pkcs8_pem(
+                &read_pem(client_cert)?,
+                &read_pem(client_key)?,
+            )
+            .map_err(|e| {
+                GemError::Config(format!("Invalid client certificate {}: {}", client_cert, e))
+            })?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(GemError::Config(
+                "client_cert and client_key need to be set together".to_string(),
+            ))
+        }
+    }
+
+    for (host, address) in &config.resolve_hosts {
+        let address = address.parse::<SocketAddr>().map_err(|_| {
+            GemError::Config(format!(
+                "Invalid address '{}' to resolve {} to",
+                address, host
+            ))
+        })?;
+        builder = builder.resolve(host, address);
+    }
+
+    if config.insecure_tls {
+        println!("{}: not verifying TLS certificates", config.label());
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder
+        .cookie_provider(jar)
+        .user_agent(user_agent(config))
+        .connect_timeout(Duration::from_secs(
+            config
+                .connect_timeout_s
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_S),
+        ))
+        .timeout(Duration::from_secs(
+            config.timeout_s.unwrap_or(DEFAULT_TIMEOUT_S),
+        ))
+        .build()
+        .map_err(|e| GemError::Fetch(format!("Unable to build HTTP client: {}", e)))
+}
+
+/// The target's `user_agent`, or Googlebot's
+pub fn user_agent(config: &Config) -> &str {
+    config.user_agent.as_deref().unwrap_or(GOOGLEBOT_USER_AGENT)
+}
+
+fn read_pem(path: &str) -> GemResult<Vec<u8>> {
+    fs::read(path).map_err(|e| GemError::Config(format!("Unable to read {}: {}", path, e)))
+}
+
+/// The request for one of the target's pages with its method, headers and body.
+pub fn build_request(config: &Config, client: &Client, url: &Url) -> GemResult<RequestBuilder> {
+    let method = parse_method(config.method.as_deref().unwrap_or("GET"))?;
+
+    let mut request = client.request(method, url.clone());
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+    if let Some(body) = &config.body {
+        request = request.body(body.clone());
+    }
+
+    Ok(request)
+}
+
+pub fn parse_method(method: &str) -> GemResult<Method> {
+    Method::from_bytes(method.trim().to_uppercase().as_bytes())
+        .map_err(|_| GemError::Config(format!("Invalid HTTP method '{}'", method)))
+}
+
+/// Fetches the target's url then any further pages from `next_page_selector` or `page_urls`, up to
+/// `max_pages` in all. `None` when the first page hasn't been modified since `cached` was saved.
+pub async fn download_pages(
+    config: &Config,
+    client: &Client,
+    cached: Option<&PageCache>,
+    is_debug: bool,
+) -> GemResult<Option<Vec<Page>>> {
+    // a conditional request can only vouch for the first page
+    let cached = cached.filter(|_| !config.is_paginated());
+    let Some(first) = download_content(config, client, &config.url, cached, is_debug).await? else {
+        return Ok(None);
+    };
+    let max_pages = config.max_pages.unwrap_or(DEFAULT_MAX_PAGES) as usize;
+    let mut pages = vec![first];
+
+    if let Some(next_page_selector) = &config.next_page_selector {
+        let selector = Selector::parse(next_page_selector).map_err(|e| {
+            GemError::Config(format!(
+                "Unable to parse next_page_selector '{}': {:?}",
+                next_page_selector, e
+            ))
+        })?;
+
+        while pages.len() < max_pages {
+            let Some(current) = pages.last() else { break };
+            let Some(next) = next_page_url(&current.body, &selector, &current.url) else {
+                break;
+            };
+            // some sites link the last page to itself or back to the start
+            if pages.iter().any(|page| page.url == next) {
+                break;
+            }
+            let Some(page) = download_content(config, client, &next, None, is_debug).await? else {
+                break;
+            };
+            pages.push(page);
+        }
+    }
+
+    'templates: for (is_counting, urls) in template_urls(config)? {
+        for url in urls {
+            if pages.len() >= max_pages {
+                break 'templates;
+            }
+
+            if !is_counting {
+                if let Some(page) = download_content(config, client, &url, None, is_debug).await? {
+                    pages.push(page);
+                }
+                continue;
+            }
+
+            // counting past the last page usually gets an error, nothing at all, or a page that's
+            // already been seen such as the first or last over again
+            let page = match download(config, client, &url, None, is_debug).await? {
+                Download::Page(page) => page,
+                Download::NotModified | Download::Refused(_) => break,
+            };
+            if page.body.trim().is_empty() || pages.iter().any(|seen| seen.body == page.body) {
+                break;
+            }
+            pages.push(page);
+        }
+    }
+
+    if pages.len() > 1 {
+        println!("Fetched {} page(s)", pages.len());
+    }
+    Ok(Some(pages))
+}
+
+/// The first `href` of an element matching the selector, relative to the page it's on.
+fn next_page_url(body: &str, selector: &Selector, current: &Url) -> Option<Url> {
+    Html::parse_document(body)
+        .select(selector)
+        .find_map(|element| element.value().attr("href"))
+        .and_then(|href| current.join(href.trim()).ok())
+}
+
+/// The urls from each of `page_urls` and whether they count up, `{page}` being replaced by 2 up
+/// to `max_pages` as the target's url is page 1.
+pub fn template_urls(config: &Config) -> GemResult<Vec<(bool, Vec<Url>)>> {
+    let max_pages = config.max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+
+    let mut templates = Vec::new();
+    for template in &config.page_urls {
+        let is_counting = template.contains(PAGE_PLACEHOLDER);
+        let expanded = match is_counting {
+            true => (2..=max_pages)
+                .map(|page| template.replace(PAGE_PLACEHOLDER, &page.to_string()))
+                .collect::<Vec<String>>(),
+            false => vec![template.clone()],
+        };
+
+        let mut urls = Vec::new();
+        for url in expanded {
+            let url = Url::parse(&url)
+                .map_err(|e| GemError::Config(format!("Invalid page url '{}': {}", url, e)))?;
+            urls.push(url);
+        }
+        templates.push((is_counting, urls));
+    }
+
+    Ok(templates)
+}
+
+/// Fetches one page, or `None` when it's not been modified since `cached` was saved. Any status
+/// other than a success is an error.
+pub async fn download_content(
+    config: &Config,
+    client: &Client,
+    url: &Url,
+    cached: Option<&PageCache>,
+    is_debug: bool,
+) -> GemResult<Option<Page>> {
+    match download(config, client, url, cached, is_debug).await? {
+        Download::Page(page) => Ok(Some(page)),
+        Download::NotModified => Ok(None),
+        // an error page isn't the content we're after
+        Download::Refused(status) => Err(GemError::Fetch(format!(
+            "Error fetching: responded with {}",
+            status
+        ))),
+    }
+}
+
+/// Network errors and any of `retry_statuses` are retried with exponential backoff, the error is
+/// only returned once every retry has failed. `timeout_s` covers every attempt and the waits
+/// between them. With `respect_robots` a page robots.txt doesn't allow is an error without trying.
+async fn download(
+    config: &Config,
+    client: &Client,
+    url: &Url,
+    cached: Option<&PageCache>,
+    is_debug: bool,
+) -> GemResult<Download> {
+    if config.respect_robots {
+        hosts::check_robots(config, client, url).await?;
+    }
+    let retries = config.retries.unwrap_or(DEFAULT_RETRIES);
+    let timeout_s = config.timeout_s.unwrap_or(DEFAULT_TIMEOUT_S);
+    let deadline = Instant::now() + Duration::from_secs(timeout_s);
+
+    let attempts = async {
+        let mut attempt = 0;
+        loop {
+            let (error, retry_after) =
+                match fetch_once(config, client, url, cached, is_debug).await? {
+                    Attempt::Fetched(page) => return Ok(Download::Page(page)),
+                    Attempt::NotModified => return Ok(Download::NotModified),
+                    Attempt::Refused(status) => return Ok(Download::Refused(status)),
+                    Attempt::Failed { error, retry_after } => (error, retry_after),
+                };
+            let delay = retry_after
+                .unwrap_or_else(|| backoff(config, attempt))
+                .min(Duration::from_secs(MAX_RETRY_DELAY_S));
+            // no point waiting for a retry that wouldn't get to start
+            if attempt >= retries || Instant::now() + delay >= deadline {
+                return Err(error);
+            }
+
+            attempt += 1;
+            println!(
+                "{}: {}: {}, retrying in {:.1}s ({} of {})",
+                config.label(),
+                url,
+                error.message(),
+                delay.as_secs_f64(),
+                attempt,
+                retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+    };
+
+    tokio::time::timeout_at(deadline, attempts)
+        .await
+        .unwrap_or_else(|_| {
+            Err(GemError::Fetch(format!(
+                "Error fetching: gave up after {}s",
+                timeout_s
+            )))
+        })
+}
+
+async fn fetch_once(
+    config: &Config,
+    client: &Client,
+    url: &Url,
+    cached: Option<&PageCache>,
+    is_debug: bool,
+) -> GemResult<Attempt> {
+    let mut request = build_request(config, client, url)?;
+    if let Some(etag) = cached.and_then(|cached| cached.etag.as_ref()) {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = cached.and_then(|cached| cached.last_modified.as_ref()) {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let _turn = hosts::wait_turn(url).await?;
+    let data = request.send().await;
+
+    if is_debug {
+        println!("{:?}", data);
+    }
+
+    let response = match data {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(Attempt::Failed {
+                error: GemError::Fetch(format!("Error fetching: {}", e)),
+                retry_after: None,
+            })
+        }
+    };
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED && cached.is_some() {
+        return Ok(Attempt::NotModified);
+    }
+    let retry_statuses = config
+        .retry_statuses
+        .as_deref()
+        .unwrap_or(DEFAULT_RETRY_STATUSES);
+    if retry_statuses.contains(&status.as_u16()) {
+        return Ok(Attempt::Failed {
+            error: GemError::Fetch(format!("Error fetching: responded with {}", status)),
+            retry_after: retry_after(&response),
+        });
+    }
+    if !status.is_success() {
+        return Ok(Attempt::Refused(status));
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    // where any redirects ended up, which is what relative links and login checks go by
+    let url = response.url().clone();
+
+    Ok(match response.text().await {
+        Ok(body) => Attempt::Fetched(Page {
+            url,
+            body,
+            etag,
+            last_modified,
+        }),
+        Err(e) => Attempt::Failed {
+            error: GemError::Fetch(format!("Error unwrapping body: {}", e)),
+            retry_after: None,
+        },
+    })
+}
+
+/// Hex SHA-256 of a page, to tell when it's changed without the server's help.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// `retry_delay_s` doubled for each retry so far, somewhere between half and all of it so
+/// targets that failed together don't all retry together.
+fn backoff(config: &Config, attempt: u32) -> Duration {
+    let delay_ms = config
+        .retry_delay_s
+        .unwrap_or(DEFAULT_RETRY_DELAY_S)
+        .saturating_mul(1000)
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_RETRY_DELAY_S * 1000);
+
+    Duration::from_millis(rand::thread_rng().gen_range(delay_ms / 2..=delay_ms))
+}
+
+/// The `Retry-After` header, given as either seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    Some((at - Utc::now()).to_std().unwrap_or_default())
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode, Url};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::config::{env_var, Config, HOST_CONCURRENCY_KEY, HOST_DELAY_S_KEY};
+use crate::error::{GemError, GemResult};
+use crate::fetch::user_agent;
+use crate::robots::RobotsTxt;
+
+/// How long a host's robots.txt is trusted before it's fetched again
+const ROBOTS_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+static HOSTS: OnceLock<Hosts> = OnceLock::new();
+
+/// Politeness settings that apply to each host across every target.
+#[derive(Clone, Copy)]
+pub struct HostLimits {
+    /// Least time between the start of one request to a host and the next
+    pub delay: Duration,
+    /// Most requests to a host in flight at once, unlimited when unset
+    pub concurrency: Option<usize>,
+}
+
+impl HostLimits {
+    /// `HOST_DELAY_S` and `HOST_CONCURRENCY`, neither limits anything when unset.
+    pub fn from_env() -> GemResult<HostLimits> {
+        let delay = env_var(HOST_DELAY_S_KEY)?.map_or(Ok(Duration::ZERO), |val| {
+            val.trim()
+                .parse::<f64>()
+                .ok()
+                // rejects negative, non-finite and too large values rather than panicking
+                .and_then(|delay_s| Duration::try_from_secs_f64(delay_s).ok())
+                .ok_or_else(|| {
+                    GemError::Config(format!(
+                        "Invalid number of seconds for {}: {}",
+                        HOST_DELAY_S_KEY, val
+                    ))
+                })
+        })?;
+        let concurrency = env_var(HOST_CONCURRENCY_KEY)?
+            .map(|val| {
+                val.trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|concurrency| *concurrency > 0)
+                    .ok_or_else(|| {
+                        GemError::Config(format!(
+                            "Invalid number for {}: {}",
+                            HOST_CONCURRENCY_KEY, val
+                        ))
+                    })
+            })
+            .transpose()?;
+
+        Ok(HostLimits { delay, concurrency })
+    }
+}
+
+/// Every host fetched from so far in this process.
+struct Hosts {
+    limits: HostLimits,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+struct Host {
+    permits: Option<Arc<Semaphore>>,
+    /// Earliest the next request may start
+    next_request: tokio::sync::Mutex<Instant>,
+    /// Held while robots.txt is fetched so targets on the same host only fetch it once
+    robots: tokio::sync::Mutex<Option<(RobotsTxt, Instant)>>,
+}
+
+/// A request's place in its host's queue, the slot is given back when it's dropped.
+pub struct HostTurn {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+fn hosts() -> GemResult<&'static Hosts> {
+    if let Some(hosts) = HOSTS.get() {
+        return Ok(hosts);
+    }
+
+    let limits = HostLimits::from_env()?;
+    Ok(HOSTS.get_or_init(|| Hosts {
+        limits,
+        hosts: Mutex::new(HashMap::new()),
+    }))
+}
+
+fn host(url: &Url) -> GemResult<Arc<Host>> {
+    let hosts = hosts()?;
+    // robots.txt only speaks for its own port so the limits go by it too
+    let key = format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default().to_lowercase(),
+        url.port_or_known_default().unwrap_or_default()
+    );
+
+    let mut entries = hosts
+        .hosts
+        .lock()
+        .map_err(|_| GemError::Fetch("Host list lock poisoned".to_string()))?;
+    let entry = entries.entry(key).or_insert_with(|| {
+        Arc::new(Host {
+            permits: hosts
+                .limits
+                .concurrency
+                .map(|concurrency| Arc::new(Semaphore::new(concurrency))),
+            next_request: tokio::sync::Mutex::new(Instant::now()),
+            robots: tokio::sync::Mutex::new(None),
+        })
+    });
+    Ok(entry.clone())
+}
+
+/// Waits for a free slot on the url's host then for `HOST_DELAY_S` to pass since the last request
+/// to it started, from any target. Hold on to the turn until the response has been read.
+pub async fn wait_turn(url: &Url) -> GemResult<HostTurn> {
+    let delay = hosts()?.limits.delay;
+    let host = host(url)?;
+
+    let permit = match &host.permits {
+        Some(permits) => Some(
+            permits
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|e| GemError::Fetch(format!("Unable to wait for {}: {}", url, e)))?,
+        ),
+        None => None,
+    };
+
+    let start = {
+        let mut next_request = host.next_request.lock().await;
+        let start = (*next_request).max(Instant::now());
+        *next_request = start + delay;
+        start
+    };
+    tokio::time::sleep_until(start).await;
+
+    Ok(HostTurn { _permit: permit })
+}
+
+/// Fails when the host's robots.txt doesn't allow the target's user agent to fetch `url`. The
+/// rules are fetched once a day per host, a missing robots.txt allows everything.
+pub async fn check_robots(config: &Config, client: &Client, url: &Url) -> GemResult<()> {
+    let host = host(url)?;
+    let mut robots = host.robots.lock().await;
+
+    let is_fresh = robots
+        .as_ref()
+        .is_some_and(|(_, fetched_at)| fetched_at.elapsed() < ROBOTS_TTL);
+    if !is_fresh {
+        *robots = Some((fetch_robots(client, url).await?, Instant::now()));
+    }
+
+    let agent = user_agent(config);
+    match robots
+        .as_ref()
+        .is_some_and(|(rules, _)| rules.is_allowed(agent, url))
+    {
+        true => Ok(()),
+        false => Err(GemError::Fetch(format!(
+            "robots.txt on {} doesn't allow fetching {}",
+            url.origin().ascii_serialization(),
+            url
+        ))),
+    }
+}
+
+async fn fetch_robots(client: &Client, url: &Url) -> GemResult<RobotsTxt> {
+    let robots_url = url
+        .join("/robots.txt")
+        .map_err(|e| GemError::Fetch(format!("Unable to find robots.txt for {}: {}", url, e)))?;
+    let unreachable = |e: &dyn std::fmt::Display| {
+        GemError::Fetch(format!("Unable to fetch {}: {}", robots_url, e))
+    };
+
+    let _turn = wait_turn(&robots_url).await?;
+    let response = client
+        .get(robots_url.clone())
+        .send()
+        .await
+        .map_err(|e| unreachable(&e))?;
+
+    let status = response.status();
+    // there are no rules without one, but a server error could be hiding some
+    if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+        return Ok(RobotsTxt::default());
+    }
+    if !status.is_success() {
+        return Err(unreachable(&format!("responded with {}", status)));
+    }
+
+    let content = response.text().await.map_err(|e| unreachable(&e))?;
+    Ok(RobotsTxt::parse(&content))
+}
@@ -0,0 +1,130 @@
+use reqwest::{Client, RequestBuilder, Url};
+use scraper::{Html, Selector};
+
+use crate::config::Config;
+use crate::error::{GemError, GemResult};
+use crate::hosts;
+
+const DEFAULT_USERNAME_FIELD: &str = "username";
+const DEFAULT_PASSWORD_FIELD: &str = "password";
+
+/// Signs in through the target's login form, leaving the session cookies in the client's jar. The
+/// login page is fetched first for its cookies and CSRF token, then the credentials are posted.
+pub async fn log_in(config: &Config, client: &Client) -> GemResult<()> {
+    let Some(login_url) = &config.login_url else {
+        return Ok(());
+    };
+    println!("{}: logging in at {}", config.label(), login_url);
+
+    let turn = hosts::wait_turn(login_url).await?;
+    let login_page = with_headers(config, client.get(login_url.clone()))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| GemError::Fetch(format!("Error fetching login page: {}", e)))?
+        .text()
+        .await
+        .map_err(|e| GemError::Fetch(format!("Error unwrapping login page: {}", e)))?;
+
+    let (username, password) = config.login_credentials()?;
+    let mut form = vec![
+        (
+            config
+                .login_username_field
+                .clone()
+                .unwrap_or(DEFAULT_USERNAME_FIELD.to_string()),
+            username,
+        ),
+        (
+            config
+                .login_password_field
+                .clone()
+                .unwrap_or(DEFAULT_PASSWORD_FIELD.to_string()),
+            password,
+        ),
+    ];
+    if let Some(login_csrf_selector) = &config.login_csrf_selector {
+        form.push(csrf_token(config, login_csrf_selector, &login_page)?);
+    }
+
+    drop(turn);
+
+    let submit_url = config.login_submit_url.as_ref().unwrap_or(login_url);
+    let _turn = hosts::wait_turn(submit_url).await?;
+    let response = with_headers(config, client.post(submit_url.clone()))
+        .form(&form)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| GemError::Fetch(format!("Error logging in: {}", e)))?;
+
+    // a rejected login usually shows the form again rather than an error status
+    if is_login_page(config, response.url()) {
+        return Err(GemError::Fetch(format!(
+            "Login rejected, still on the login page {}",
+            response.url()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Whether `url` is the target's login page, ignoring any query such as a return url.
+pub fn is_login_page(config: &Config, url: &Url) -> bool {
+    config.login_url.as_ref().is_some_and(|login_url| {
+        login_url.scheme() == url.scheme()
+            && login_url.host_str() == url.host_str()
+            && login_url.port_or_known_default() == url.port_or_known_default()
+            && login_url.path() == url.path()
+    })
+}
+
+/// Field name and value of the CSRF token, from the element's `value`, `content` (for a meta tag)
+/// or text.
+fn csrf_token(
+    config: &Config,
+    login_csrf_selector: &str,
+    login_page: &str,
+) -> GemResult<(String, String)> {
+    let selector = Selector::parse(login_csrf_selector).map_err(|e| {
+        GemError::Config(format!(
+            "Unable to parse login_csrf_selector '{}': {:?}",
+            login_csrf_selector, e
+        ))
+    })?;
+
+    let document = Html::parse_document(login_page);
+    let element = document.select(&selector).next().ok_or_else(|| {
+        GemError::Fetch(format!(
+            "No CSRF token on the login page matching '{}'",
+            login_csrf_selector
+        ))
+    })?;
+
+    let value = element
+        .value()
+        .attr("value")
+        .or_else(|| element.value().attr("content"))
+        .map(str::to_string)
+        .unwrap_or_else(|| element.text().collect::<String>().trim().to_string());
+    let field = config
+        .login_csrf_field
+        .as_deref()
+        .or_else(|| element.value().attr("name"))
+        .ok_or_else(|| {
+            GemError::Config(format!(
+                "The CSRF token element has no name, set login_csrf_field for '{}'",
+                login_csrf_selector
+            ))
+        })?;
+
+    Ok((field.to_string(), value))
+}
+
+/// The target's own headers go on the login requests too, some sites want them throughout.
+fn with_headers(config: &Config, mut request: RequestBuilder) -> RequestBuilder {
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+    request
+}
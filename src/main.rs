@@ -1,80 +1,695 @@
-use std::env;
-use std::fmt::Display;
-use std::fs::{self, remove_file, File};
+mod changes;
+mod config;
+mod daemon;
+mod error;
+mod export;
+mod fetch;
+mod hosts;
+mod login;
+mod matchers;
+mod quiet_hours;
+mod rate_limit;
+mod robots;
+mod state;
+
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::path::Path;
-use std::str::FromStr;
 
-use chrono::{prelude::*, Duration};
-use scraper::{Html, Selector};
+use chrono::{DateTime, Utc};
+
+use clap::builder::FalseyValueParser;
+use clap::{Parser, Subcommand};
 
 use lettre::message::{header, Mailbox, MultiPart, SinglePart};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
-use reqwest::header::USER_AGENT;
-use reqwest::{self, StatusCode, Url};
+use reqwest::{self, StatusCode};
 
 use derive_builder::Builder;
 
 use serde::Serialize;
 
-const TARGET_URL_KEY: &str = "TARGET_URL";
-const SEARCH_TEXT_KEY: &str = "SEARCH_TEXT";
-const CONTENT_TYPE_KEY: &str = "CONTENT_TYPE";
-const SELECTOR_KEY: &str = "SELECTOR";
+use changes::{diff_matches, Fingerprint};
+use config::*;
+use error::{GemError, GemResult};
+use export::{ExportFormat, ExportOptions};
+use fetch::{content_hash, Page, TargetClient};
+use matchers::{escape_html, find_matches, Match};
+use state::{PageCache, RunResult, StateStore, MAX_RUNS};
+
+#[derive(Parser)]
+#[command(version, about = "A simple app to look for things in places")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file with a [[target]] table per page, otherwise a single target is read from env
+    #[arg(long, global = true, env = CONFIG_FILE_KEY)]
+    config: Option<String>,
+
+    /// Only use the target with this name or url
+    #[arg(long, global = true)]
+    target: Option<String>,
+
+    /// Log more about what's going on and write the emails to tmp/
+    #[arg(long, global = true, env = DEBUG_KEY, value_parser = FalseyValueParser::new())]
+    debug: bool,
+
+    /// Log emails instead of sending them
+    #[arg(long, global = true, env = PREVENT_EMAIL_KEY, value_parser = FalseyValueParser::new())]
+    prevent_email: bool,
+
+    /// Log Signal messages instead of sending them
+    #[arg(long, global = true, env = PREVENT_MESSAGE_KEY, value_parser = FalseyValueParser::new())]
+    prevent_message: bool,
+}
 
-const NOTIFICATION_TYPE_KEY: &str = "NOTIFICATION_TYPE";
-const NOTIFICATION_MAX_PER_INTERVAL_KEY: &str = "NOTIFICATION_MAX_PER_INTERVAL";
-const NOTIFICATION_INTERVAL_S_KEY: &str = "NOTIFICATION_INTERVAL_S";
-const NOTIFICATION_WRITE_DIR_KEY: &str = "NOTIFICATION_WRITE_DIR";
+#[derive(Subcommand)]
+enum Command {
+    /// Check every target once and notify about any matches, the default
+    Run,
+    /// Keep running and check each target on its own schedule
+    Daemon,
+    /// Report every problem with the config without fetching anything
+    CheckConfig,
+    /// Download a target's page and save it to a file
+    Fetch {
+        /// Where to save the page
+        #[arg(long, short, default_value = "content.html")]
+        output: String,
+    },
+    /// Search a saved page using a target's matcher
+    Match {
+        /// Page to search, e.g. one saved by `gem fetch`
+        #[arg(long, short)]
+        file: String,
+    },
+    /// Send a made up match through every configured notification type
+    NotifyTest,
+    /// Print a deployment config using the current settings, with secrets split out
+    Export {
+        #[arg(long, short, value_enum)]
+        format: ExportFormat,
+        /// Name for the job, service or unit
+        #[arg(long, default_value = "gem")]
+        name: String,
+        /// Docker image to run
+        #[arg(long, default_value = "hub/gem:latest")]
+        image: String,
+        /// Also write each secret to a file in this directory, ready to be mounted
+        #[arg(long)]
+        secrets_dir: Option<String>,
+    },
+}
 
-const DEBUG_KEY: &str = "DEBUG";
-const PREVENT_EMAIL_KEY: &str = "PREVENT_EMAIL";
-const PREVENT_MESSAGE_KEY: &str = "PREVENT_MESSAGE";
+/// Flags that apply to the whole run rather than a single target.
+#[derive(Clone, Copy)]
+pub struct RunOptions {
+    pub is_debug: bool,
+    pub prevent_email: bool,
+    pub prevent_message: bool,
+}
 
-const SMTP_USER_KEY: &str = "SMTP_USER";
-const SMTP_PASS_KEY: &str = "SMTP_PASS";
-const SMTP_RELAY_KEY: &str = "SMTP_RELAY";
+#[tokio::main]
+async fn main() {
+    // load .env first so it can supply the flags below
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
 
-const EMAIL_TO_KEY: &str = "EMAIL_TO";
-const EMAIL_FROM_KEY: &str = "EMAIL_FROM";
+    let options = RunOptions {
+        is_debug: cli.debug,
+        prevent_email: cli.prevent_email,
+        prevent_message: cli.prevent_message,
+    };
+    if options.is_debug && !Path::new("tmp").exists() {
+        if let Err(error) = fs::create_dir("tmp") {
+            println!("can't create debug dir: {}", error);
+        }
+    }
 
-const SIGNAL_URL_KEY: &str = "SIGNAL_URL";
-const SIGNAL_SENDER_KEY: &str = "SIGNAL_SENDER";
-const SIGNAL_RECIPIENTS_KEY: &str = "SIGNAL_RECIPIENTS";
-const SIGNAL_MESSAGE_PREFIX_KEY: &str = "SIGNAL_MESSAGE_PREFIX";
+    let command = cli.command.unwrap_or(Command::Run);
+    // export output is meant to be piped into a file so keep it clean
+    let is_export = matches!(command, Command::Export { .. });
+
+    let result = match command {
+        Command::Run => run_once(&cli.config, &cli.target, options).await,
+        Command::Daemon => match load_targets(&cli.config, &cli.target) {
+            Ok(configs) => daemon::run_daemon(configs, options).await,
+            Err(error) => Err(error),
+        },
+        Command::CheckConfig => check_config(&cli.config),
+        Command::Fetch { output } => fetch(&cli.config, &cli.target, &output, options).await,
+        Command::Match { file } => match_file(&cli.config, &cli.target, &file),
+        Command::NotifyTest => notify_test(&cli.config, &cli.target, options).await,
+        Command::Export {
+            format,
+            name,
+            image,
+            secrets_dir,
+        } => export::export(
+            &cli.config,
+            &ExportOptions {
+                format,
+                name,
+                image,
+                secrets_dir,
+            },
+        )
+        .map(|artifact| print!("{}", artifact)),
+    };
 
-// Expansions:
-// regex
-// xpath
-// jq structure
-// json type
-// csv ..
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        std::process::exit(error.exit_code());
+    }
 
-#[tokio::main]
-async fn main() {
-    dotenv::dotenv().ok();
-    let is_debug = env::var(DEBUG_KEY).is_ok();
-    if is_debug && !Path::new("tmp").exists() {
-        fs::create_dir("tmp").expect("can't create debug dir");
+    if !is_export {
+        println!("Finished");
     }
+}
 
-    let config = load_config();
-    let content = download_content(&config, is_debug).await;
+/// Loads the configured targets, keeping only the one picked with `--target` if given.
+fn load_targets(config_file: &Option<String>, target: &Option<String>) -> GemResult<Vec<Config>> {
+    let configs = load_configs(config_file.as_deref())?;
 
-    if is_debug {
-        println!("Content {}", content);
-        let mut f = File::create("tmp/content.html").unwrap();
-        f.write_all(content.as_bytes()).unwrap();
-        f.sync_data().unwrap();
+    let Some(target) = target else {
+        return Ok(configs);
+    };
+
+    // the configured url has been normalised, e.g. given a trailing slash, so parse ours too
+    let target_url = reqwest::Url::parse(target).ok();
+    let selected = configs
+        .into_iter()
+        .filter(|config| {
+            config.name.as_ref() == Some(target) || target_url.as_ref() == Some(&config.url)
+        })
+        .collect::<Vec<Config>>();
+
+    if selected.is_empty() {
+        return Err(GemError::Config(format!("No target named {}", target)));
+    }
+
+    Ok(selected)
+}
+
+/// For commands that only make sense against one page.
+fn load_single_target(config_file: &Option<String>, target: &Option<String>) -> GemResult<Config> {
+    let mut configs = load_targets(config_file, target)?;
+
+    if configs.len() > 1 {
+        return Err(GemError::Config(format!(
+            "Found {} targets, pick one with --target",
+            configs.len()
+        )));
+    }
+
+    Ok(configs.remove(0))
+}
+
+fn check_config(config_file: &Option<String>) -> GemResult<()> {
+    let ConfigReport {
+        targets: reports,
+        shared_problems,
+    } = check_configs(config_file.as_deref())?;
+
+    let mut problem_count = 0;
+    for report in &reports {
+        if report.problems.is_empty() {
+            println!("ok      {}", report.label);
+            continue;
+        }
+
+        println!("FAILED  {}", report.label);
+        for problem in &report.problems {
+            println!("        - {}", problem);
+        }
+        problem_count += report.problems.len();
+    }
+
+    if !shared_problems.is_empty() {
+        println!("FAILED  shared settings");
+        for problem in &shared_problems {
+            println!("        - {}", problem);
+        }
+        problem_count += shared_problems.len();
+    }
+
+    if problem_count > 0 {
+        return Err(GemError::Config(format!(
+            "Found {} problem(s) across {} target(s)",
+            problem_count,
+            reports.len()
+        )));
+    }
+
+    println!("Config OK for {} target(s)", reports.len());
+    Ok(())
+}
+
+/// Checks every target once, carrying on past failures and returning the first one.
+async fn run_once(
+    config_file: &Option<String>,
+    target: &Option<String>,
+    options: RunOptions,
+) -> GemResult<()> {
+    let configs = load_targets(config_file, target)?;
+    let client = reqwest::Client::new();
+    let store = state::open_store()?;
+
+    let mut first_error = None;
+    for config in &configs {
+        let result = match target_client(config, store.as_ref()) {
+            Ok(target) => check_target(config, &target, &client, store.as_ref(), options).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            eprintln!("{}: {}", config.label(), error);
+            first_error.get_or_insert(error);
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+async fn fetch(
+    config_file: &Option<String>,
+    target: &Option<String>,
+    output: &str,
+    options: RunOptions,
+) -> GemResult<()> {
+    let config = load_single_target(config_file, target)?;
+    let store = state::open_store()?;
+    let target = TargetClient::new(&config, None)?;
+    reported(
+        login::log_in(&config, &target.client).await,
+        &config,
+        store.as_ref(),
+    )?;
+    let page = reported(
+        fetch::download_content(&config, &target.client, &config.url, None, options.is_debug).await,
+        &config,
+        store.as_ref(),
+    )?
+    .ok_or_else(|| GemError::Fetch("Server responded 304 Not Modified".to_string()))?;
+    fs::write(output, &page.body)
+        .map_err(|e| GemError::State(format!("Unable to write {}: {}", output, e)))?;
+
+    println!("Saved {} to {}", config.url, output);
+    Ok(())
+}
+
+fn match_file(config_file: &Option<String>, target: &Option<String>, file: &str) -> GemResult<()> {
+    let config = load_single_target(config_file, target)?;
+    let content = fs::read_to_string(file)
+        .map_err(|e| GemError::Parse(format!("Unable to read {}: {}", file, e)))?;
+
+    let matches = find_matches(&content, &config)?;
+
+    println!("Found {} match(es)", matches.len());
+    for result in &matches {
+        println!("\n{}\n", result);
+    }
+
+    Ok(())
+}
+
+async fn notify_test(
+    config_file: &Option<String>,
+    target: &Option<String>,
+    options: RunOptions,
+) -> GemResult<()> {
+    let configs = load_targets(config_file, target)?;
+    let client = reqwest::Client::new();
+
+    let matches = vec![Match::html(String::from(
+        "<p>This is a test notification from <b>gem notify-test</b></p>",
+    ))];
+
+    let mut first_error = None;
+    for config in &configs {
+        println!("Sending test notification for {}", config.label());
+        let headline = format!("Test notification for {}", config.url);
+        let result = notify(
+            &headline,
+            &matches,
+            config,
+            &config.notification_types,
+            &client,
+            options,
+        )
+        .await;
+        if let Err(error) = result {
+            first_error.get_or_insert(error);
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+/// The client a target fetches with for as long as gem runs, picking up the login session saved by
+/// an earlier run.
+pub fn target_client(config: &Config, store: &dyn StateStore) -> GemResult<TargetClient> {
+    let saved = match config.login_url {
+        Some(_) => store.session(&config.id())?,
+        None => None,
+    };
+    TargetClient::new(config, saved.as_deref())
+}
+
+pub async fn check_target(
+    config: &Config,
+    target: &TargetClient,
+    client: &reqwest::Client,
+    store: &dyn StateStore,
+    options: RunOptions,
+) -> GemResult<()> {
+    let is_debug = options.is_debug;
+    let target_id = config.id();
+    println!("Checking {}", config.label());
+
+    let fetched = match fetch_matches(config, target, store, is_debug).await {
+        Ok(fetched) => fetched,
+        Err(error) => {
+            if let Err(state_error) = store.record_run(&target_id, &RunResult::failed(&error)) {
+                eprintln!("Unable to record run: {}", state_error);
+            }
+            return Err(error);
+        }
+    };
+
+    let Some((matches, page_cache)) = fetched else {
+        // the page is as it was so the matches are too
+        let match_count = store
+            .recent_runs(&target_id, MAX_RUNS)?
+            .iter()
+            .find(|run| run.error.is_none())
+            .map_or(0, |run| run.match_count);
+        store.record_run(&target_id, &RunResult::succeeded(match_count))?;
+        return send_held_summaries(config, client, store, options).await;
+    };
+    store.record_run(&target_id, &RunResult::succeeded(matches.len()))?;
+
+    send_held_summaries(config, client, store, options).await?;
+
+    let is_steady = is_steady(config, store, matches.is_empty())?;
+    notify_matches(config, client, store, options, &matches, is_steady).await?;
+
+    // only skip the page once it's been dealt with, so it's looked at again after a failed send or
+    // while require_consecutive is still counting
+    if let Some(page_cache) = page_cache.filter(|_| is_steady) {
+        store.save_page_cache(&target_id, &page_cache)?;
+    }
+
+    Ok(())
+}
+
+/// Works out what this run's matches mean for the target and notifies about it.
+async fn notify_matches(
+    config: &Config,
+    client: &reqwest::Client,
+    store: &dyn StateStore,
+    options: RunOptions,
+    matches: &[Match],
+    is_steady: bool,
+) -> GemResult<()> {
+    let target_id = config.id();
+
+    // the target is triggered while whatever it notifies about holds, i.e. while there are matches
+    // or, in absent mode, while there are none
+    let is_triggered = (config.match_mode == MatchMode::Present) != matches.is_empty();
+    let triggered_since = store.triggered_since(&target_id)?;
+    if is_steady && is_triggered != triggered_since.is_some() {
+        if let Some(triggered_since) = triggered_since.filter(|_| config.notify_recovery) {
+            send_recovery(config, client, store, options, matches, triggered_since).await?;
+        }
+        store.save_triggered_since(&target_id, is_triggered.then(Utc::now))?;
+    }
+
+    let is_tracking_changes =
+        config.match_mode == MatchMode::Present && config.notify_on == NotifyOn::Changes;
+
+    let (headline, notified_matches) = match config.match_mode {
+        MatchMode::Present if is_tracking_changes => {
+            let changes = diff_matches(&store.fingerprints(&target_id)?, matches);
+            if changes.is_empty() {
+                println!("No changes to {} match(es)", matches.len());
+                return Ok(());
+            }
+            let headline = format!("Matches changed at {}: {}", config.url, changes.summary());
+            (headline, changes.matches)
+        }
+        MatchMode::Present if matches.is_empty() => {
+            println!("No matches");
+            return Ok(());
+        }
+        MatchMode::Present => (
+            format!("Found {} match(es) at {}", matches.len(), config.url),
+            matches.to_vec(),
+        ),
+        MatchMode::Absent if !matches.is_empty() => {
+            println!("Still found {} match(es)", matches.len());
+            return Ok(());
+        }
+        MatchMode::Absent => (
+            format!("Nothing matched {} at {}", config.matcher(), config.url),
+            Vec::new(),
+        ),
+    };
+
+    if !is_steady {
+        println!(
+            "Not notifying until {} runs in a row agree",
+            config.require_consecutive.unwrap_or(1)
+        );
+        return Ok(());
+    }
+
+    let mut tokens = Vec::new();
+    let mut is_held = false;
+    for channel in &config.notification_types {
+        if quiet_hours::hold_if_quiet(store, config, channel, &headline, &notified_matches)? {
+            is_held = true;
+        } else if let Some(token) = rate_limit::take_token(store, config, channel)? {
+            tokens.push(token);
+        }
+    }
+
+    if !tokens.is_empty() {
+        println!("Notifying...");
+        let channels = tokens
+            .iter()
+            .map(|token| token.channel.clone())
+            .collect::<Vec<NotificationType>>();
+        let results = notify_each(
+            &headline,
+            &notified_matches,
+            config,
+            &channels,
+            client,
+            options,
+        )
+        .await;
+
+        // a channel that failed keeps its token for the retry
+        let mut first_error = None;
+        for (token, result) in tokens.into_iter().zip(results) {
+            match result {
+                Ok(()) => token.spend(store)?,
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        if let Some(error) = first_error {
+            return Err(error);
+        }
+    } else if !is_held {
+        println!("Every channel is rate limited");
+        return Ok(());
+    }
+
+    // only remember matches once they've been sent so a failed send is retried next run
+    if is_tracking_changes {
+        let fingerprints = matches
+            .iter()
+            .map(Fingerprint::new)
+            .collect::<Vec<Fingerprint>>();
+        store.save_fingerprints(&target_id, &fingerprints)?;
     }
 
-    let matches = match config.content_type {
-        ContentType::Html => parse_html_and_search(&content, &config),
-        ContentType::Text => search_for_text(&content, &config),
+    Ok(())
+}
+
+/// Whether enough of the latest runs, this one included, agree on whether there were matches to
+/// satisfy `require_consecutive`.
+fn is_steady(config: &Config, store: &dyn StateStore, is_empty: bool) -> GemResult<bool> {
+    let required = config.require_consecutive.unwrap_or(1) as usize;
+    if required <= 1 {
+        return Ok(true);
+    }
+
+    let streak = store
+        .recent_runs(&config.id(), required)?
+        .iter()
+        .take_while(|run| run.error.is_none() && (run.match_count == 0) == is_empty)
+        .count();
+    Ok(streak >= required)
+}
+
+/// Sends an all clear on every channel that isn't in its quiet hours, rate limits aside as there's
+/// only one per trigger.
+async fn send_recovery(
+    config: &Config,
+    client: &reqwest::Client,
+    store: &dyn StateStore,
+    options: RunOptions,
+    matches: &[Match],
+    triggered_since: DateTime<Utc>,
+) -> GemResult<()> {
+    let since = triggered_since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let headline = match config.match_mode {
+        MatchMode::Present => format!(
+            "All clear: nothing matches {} at {} any more, matching since {}",
+            config.matcher(),
+            config.url,
+            since
+        ),
+        MatchMode::Absent => format!(
+            "All clear: found {} match(es) for {} at {} again, missing since {}",
+            matches.len(),
+            config.matcher(),
+            config.url,
+            since
+        ),
+    };
+
+    // in absent mode the matches that turned up are what cleared it
+    let matches = match config.match_mode {
+        MatchMode::Present => &[][..],
+        MatchMode::Absent => matches,
+    };
+
+    let mut channels = Vec::new();
+    for channel in &config.notification_types {
+        if !quiet_hours::hold_if_quiet(store, config, channel, &headline, matches)? {
+            channels.push(channel.clone());
+        }
+    }
+    if channels.is_empty() {
+        return Ok(());
+    }
+
+    println!("Sending all clear...");
+    notify(&headline, matches, config, &channels, client, options).await
+}
+
+/// Sends one summary per channel whose quiet hours have ended with notifications held from them.
+async fn send_held_summaries(
+    config: &Config,
+    client: &reqwest::Client,
+    store: &dyn StateStore,
+    options: RunOptions,
+) -> GemResult<()> {
+    let target_id = config.id();
+
+    for channel in &config.notification_types {
+        let Some(held) = quiet_hours::due_summary(store, config, channel)? else {
+            continue;
+        };
+        // stays queued for the next check when there's no token for it yet
+        let Some(token) = rate_limit::take_token(store, config, channel)? else {
+            continue;
+        };
+
+        println!(
+            "Sending {} {} notif(s) held during quiet hours",
+            held.held, channel
+        );
+        let headline = held.headline(&config.label());
+        let result = notify(
+            &headline,
+            &held.matches,
+            config,
+            std::slice::from_ref(channel),
+            client,
+            options,
+        )
+        .await;
+        // keep them for the next check rather than skip this one
+        if let Err(error) = result {
+            eprintln!("Unable to send quiet hours summary: {}", error);
+            continue;
+        }
+        token.spend(store)?;
+        store.save_held(&target_id, channel, None)?;
+    }
+
+    Ok(())
+}
+
+/// Downloads and searches the target's page, or `None` with `skip_unchanged` when the page is the
+/// same as last time. The page's new cache entry comes back with the matches, to be saved once
+/// they've been dealt with.
+async fn fetch_matches(
+    config: &Config,
+    target: &TargetClient,
+    store: &dyn StateStore,
+    is_debug: bool,
+) -> GemResult<Option<(Vec<Match>, Option<PageCache>)>> {
+    let cached = match config.skip_unchanged {
+        true => store.page_cache(&config.id())?,
+        false => None,
+    };
+
+    let pages = reported(
+        download_pages(config, target, store, cached.as_ref(), is_debug).await,
+        config,
+        store,
+    )?;
+    let Some(pages) = pages else {
+        println!("Not modified since the last check");
+        return Ok(None);
     };
 
+    let page_cache = config.skip_unchanged.then(|| PageCache {
+        etag: pages[0].etag.clone(),
+        last_modified: pages[0].last_modified.clone(),
+        hash: content_hash(
+            &pages
+                .iter()
+                .map(|page| page.body.as_str())
+                .collect::<Vec<&str>>()
+                .join("\0"),
+        ),
+    });
+    let is_unchanged = cached
+        .as_ref()
+        .zip(page_cache.as_ref())
+        .is_some_and(|(cached, page_cache)| cached.hash == page_cache.hash);
+    if is_unchanged {
+        println!("Content unchanged since the last check");
+        return Ok(None);
+    }
+
+    let mut matches = Vec::new();
+    for page in &pages {
+        if is_debug {
+            println!("Content {}", page.body);
+        }
+
+        let mut page_matches = find_matches(&page.body, config)?;
+        if config.is_paginated() {
+            for found in &mut page_matches {
+                found
+                    .fields
+                    .push(("page".to_string(), page.url.to_string()));
+            }
+        }
+        matches.append(&mut page_matches);
+    }
+
     if is_debug {
         println!("Found {} match(es)", matches.len());
         println!("\nResults");
@@ -83,57 +698,136 @@ async fn main() {
         }
     }
 
-    let has_matches = !matches.is_empty();
+    Ok(Some((matches, page_cache)))
+}
+
+/// Fetches the target's pages, signed in when it has a login form. The session is saved for later
+/// checks and runs, and only logged into again when there isn't one or the target sends us back to
+/// the login page.
+async fn download_pages(
+    config: &Config,
+    target: &TargetClient,
+    store: &dyn StateStore,
+    cached: Option<&PageCache>,
+    is_debug: bool,
+) -> GemResult<Option<Vec<Page>>> {
+    let client = &target.client;
+    if config.login_url.is_none() {
+        return fetch::download_pages(config, client, cached, is_debug).await;
+    }
+
+    let is_on_login_page = |pages: &Option<Vec<Page>>| {
+        pages
+            .as_ref()
+            .and_then(|pages| pages.first())
+            .is_some_and(|page| login::is_login_page(config, &page.url))
+    };
 
-    if !has_matches {
-        println!("No matches");
-        return;
+    let mut cached = cached;
+    if target.has_session() {
+        let pages = fetch::download_pages(config, client, cached, is_debug).await?;
+        if !is_on_login_page(&pages) {
+            store.save_session(&config.id(), &target.save_session()?)?;
+            return Ok(pages);
+        }
+        println!("{}: session expired, logging in again", config.label());
+        cached = None;
     }
 
-    if !check_last_send_time(&config, is_debug).unwrap_or(false) {
-        println!("Sent recent message or passed threshold");
-        return;
+    // start from a clean jar so nothing from the old session gets in the way
+    target.clear_session(config)?;
+    login::log_in(config, client).await?;
+    let pages = fetch::download_pages(config, client, cached, is_debug).await?;
+    if is_on_login_page(&pages) {
+        return Err(GemError::Fetch(
+            "Sent back to the login page straight after logging in".to_string(),
+        ));
     }
 
-    println!("Notifying...");
+    store.save_session(&config.id(), &target.save_session()?)?;
+    Ok(pages)
+}
 
-    let tasks = config
-        .notification_types
-        .clone()
+/// Sends the headline and matches through each of the given notification types.
+async fn notify(
+    headline: &str,
+    matches: &[Match],
+    config: &Config,
+    channels: &[NotificationType],
+    client: &reqwest::Client,
+    options: RunOptions,
+) -> GemResult<()> {
+    notify_each(headline, matches, config, channels, client, options)
+        .await
         .into_iter()
+        .collect()
+}
+
+/// Sends on every channel at once, giving back each one's result in the order of `channels`.
+async fn notify_each(
+    headline: &str,
+    matches: &[Match],
+    config: &Config,
+    channels: &[NotificationType],
+    client: &reqwest::Client,
+    options: RunOptions,
+) -> Vec<GemResult<()>> {
+    let tasks = channels
+        .iter()
         .map(|notif_type| {
-            let matches = matches.clone();
-            let is_debug = is_debug.clone();
+            let headline = headline.to_string();
+            let matches = matches.to_vec();
             let config = config.clone();
+            let client = client.clone();
             match notif_type {
                 NotificationType::Email => tokio::spawn(async move {
                     email_result(
+                        &headline,
                         &matches,
                         &config,
-                        is_debug,
-                        env::var(PREVENT_EMAIL_KEY).is_ok(),
+                        options.is_debug,
+                        options.prevent_email,
                     )
                 }),
                 NotificationType::Signal => tokio::spawn(async move {
                     message_to_signal_result(
+                        &headline,
                         &matches,
                         &config,
-                        is_debug,
-                        env::var(PREVENT_MESSAGE_KEY).is_ok(),
+                        &client,
+                        options.is_debug,
+                        options.prevent_message,
                     )
                     .await
-                    .ok();
                 }),
             }
         })
-        .collect::<Vec<tokio::task::JoinHandle<()>>>();
+        .collect::<Vec<tokio::task::JoinHandle<GemResult<()>>>>();
 
+    // let every channel have its go before reporting the first failure
+    let mut results = Vec::new();
     for task in tasks {
-        task.await.unwrap();
+        let result = task
+            .await
+            .unwrap_or_else(|e| Err(GemError::Notify(format!("Notification task failed: {}", e))));
+        if let Err(error) = &result {
+            eprintln!("{}", error);
+        }
+        results.push(result);
     }
 
-    // else
-    println!("Finished");
+    results
+}
+
+fn write_debug_file(filename: &str, contents: &str) {
+    let result = File::create(filename).and_then(|mut f| {
+        f.write_all(contents.as_bytes())?;
+        f.sync_data()
+    });
+
+    if let Err(error) = result {
+        println!("Unable to write debug file {}: {}", filename, error);
+    }
 }
 
 #[derive(Serialize, Builder)]
@@ -144,35 +838,62 @@ struct SignalMessage<'a> {
     message: &'a str,
 }
 
+/// Most of a match's content shown in a Signal message, the email has the rest
+const SIGNAL_SNIPPET_CHARS: usize = 100;
+
 async fn message_to_signal_result(
-    matches: &[String],
+    headline: &str,
+    matches: &[Match],
     config: &Config,
+    client: &reqwest::Client,
     is_debug: bool,
     prevent_message: bool,
-) -> reqwest::Result<()> {
-    let count_of_matches = matches.len();
-
+) -> GemResult<()> {
     let text_mode = "styled";
-    let number = config.signal_sender.as_ref().unwrap();
+    let number = config
+        .signal_sender
+        .as_ref()
+        .ok_or_else(|| GemError::Config("Need SIGNAL_SENDER to send notifs".to_string()))?;
+    let signal_url = config
+        .signal_url
+        .as_ref()
+        .ok_or_else(|| GemError::Config("Need SIGNAL_URL to send notifs".to_string()))?;
     let recipients = &config.signal_recipients;
-    let message = format!(
-        "{}Found {} match(es) at {}",
+    let mut message = format!(
+        "{}{}",
         config.signal_message.as_ref().unwrap_or(&String::new()),
-        count_of_matches,
-        config.url
+        headline
     );
+    for result in matches {
+        // matches told apart by their content need some of it to mean anything
+        let line = match (result.key.is_none(), result.fields.is_empty()) {
+            (true, true) => result.snippet(SIGNAL_SNIPPET_CHARS),
+            (true, false) => format!(
+                "{}: {}",
+                result.fields_summary(),
+                result.snippet(SIGNAL_SNIPPET_CHARS)
+            ),
+            (false, false) => result.fields_summary(),
+            (false, true) => continue,
+        };
+        message.push('\n');
+        message.push_str(&line);
+    }
 
     let new_message = SignalMessageBuilder::default()
-        .text_mode(&text_mode)
+        .text_mode(text_mode)
         .message(&message)
         .recipients(recipients)
         .number(number)
         .message(&message)
         .build()
-        .expect("Unable to build Signal message");
+        .map_err(|e| GemError::Notify(format!("Unable to build Signal message: {}", e)))?;
+
+    let body = serde_json::to_string(&new_message)
+        .map_err(|e| GemError::Notify(format!("Error stringifying SignalMessage: {}", e)))?;
 
     if is_debug {
-        println!("{}", serde_json::to_string(&new_message).unwrap());
+        println!("{}", body);
     }
 
     //todo: load bytes for image?
@@ -185,117 +906,44 @@ async fn message_to_signal_result(
     if prevent_message {
         println!(
             "PREVENT_MESSAGE set - not sending message\n{}\n",
-            serde_json::to_string_pretty(&new_message).unwrap()
+            serde_json::to_string_pretty(&new_message).unwrap_or(body)
         );
         return Ok(());
     }
 
-    let client = reqwest::Client::new();
-
     let result = client
-        .post(config.signal_url.as_ref().unwrap().to_owned())
-        .body(serde_json::to_string(&new_message).expect("Error stringifying SignalMessage"))
+        .post(signal_url.to_owned())
+        .body(body.clone())
         .send()
-        .await?;
+        .await
+        .map_err(|e| GemError::Notify(format!("Error sending Signal message: {}", e)))?;
 
     println!("Signal status {}", result.status());
 
     if is_debug || result.status() != StatusCode::CREATED {
-        println!("Request: {}", serde_json::to_string(&new_message).unwrap());
+        println!("Request: {}", body);
         println!("Response: {:?}", result);
     }
 
-    Ok(())
-}
-
-const DEFAULT_NOTIFICATION_INTERVAL: u32 = 60 * 5; //5 minutes
-const DEFAULT_MAX_SEND: u8 = 3;
-const DEFAULT_NOTIFICATION_WRITE_DIR: &str = "./";
-fn check_last_send_time(config: &Config, is_debug: bool) -> std::io::Result<bool> {
-    let notif_interval =
-        env::var(NOTIFICATION_INTERVAL_S_KEY).map_or(DEFAULT_NOTIFICATION_INTERVAL, |val| {
-            val.parse::<u32>()
-                .expect("Invalid number for notif interval")
-        });
-    let max_sent = env::var(NOTIFICATION_MAX_PER_INTERVAL_KEY).map_or(DEFAULT_MAX_SEND, |val| {
-        val.parse::<u8>().expect("Invalid number for max notif")
-    });
-
-    let notification_write_dir =
-        env::var(NOTIFICATION_WRITE_DIR_KEY).unwrap_or(DEFAULT_NOTIFICATION_WRITE_DIR.to_string());
-
-    let filename = format!(
-        "{}last_checked-{}",
-        notification_write_dir,
-        config.url.domain().or(Some("")).unwrap()
-    );
-
-    if is_debug {
-        println!("{}", &filename);
-    }
-
-    if !Path::new(&filename).exists() {
-        save_last_send_time(&filename, 1)?;
-        println!("Creating last send time");
-        return Ok(true);
-    }
-
-    // load file
-    let mut file = File::open(&filename)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let parts = contents.split("|").collect::<Vec<&str>>();
-
-    if parts.len() != 2 {
-        // can't parse file so log it, delete and send email
-        println!("Unexpected format {}", contents);
-        remove_file(&filename)?;
-        return Ok(true);
+    if !result.status().is_success() {
+        return Err(GemError::Notify(format!(
+            "Signal responded with {}",
+            result.status()
+        )));
     }
 
-    let last_send_time_seconds = parts
-        .get(0)
-        .map(|val| {
-            val.parse::<i64>()
-                .expect(&format!("Unable to parse timestamp to int {}", val))
-        })
-        .unwrap();
-
-    let last_send_time =
-        DateTime::from_timestamp(last_send_time_seconds, 0).expect("Unable to create DateTime");
-
-    let total_send_count = parts
-        .get(1)
-        .map(|val| val.parse::<u8>().expect("Unable to parse count"))
-        .unwrap();
-
-    let is_last_send_outside_interval =
-        Utc::now() > last_send_time + Duration::seconds(notif_interval.into());
-    let is_total_lower_than_threshold = total_send_count < max_sent;
-
-    if is_total_lower_than_threshold {
-        save_last_send_time(&filename, total_send_count + 1)?;
-        println!("sent {} out of {} notifs", total_send_count, max_sent);
-        Ok(true)
-    } else if is_last_send_outside_interval {
-        save_last_send_time(&filename, 1)?;
-        println!("last sent more than {}s ago", notif_interval);
-        Ok(true)
-    } else {
-        println!("not sending notif");
-        Ok(false)
-    }
-}
-
-fn save_last_send_time(filename: &str, count: u8) -> std::io::Result<bool> {
-    let mut file = File::create(filename)?;
-    file.write_all(format!("{}|{}", Utc::now().timestamp(), count).as_bytes())?;
-    Ok(true)
+    Ok(())
 }
 
-fn email_result(matches: &[String], config: &Config, is_debug: bool, prevent_email: bool) {
+fn email_result(
+    headline: &str,
+    matches: &[Match],
+    config: &Config,
+    is_debug: bool,
+    prevent_email: bool,
+) -> GemResult<()> {
     let url = &config.url;
-    let subject = format!("Found {} match(es) for {}", matches.len(), url);
+    let subject = headline;
 
     let mut html_body = String::from(
         r#"<!DOCTYPE html>
@@ -316,35 +964,57 @@ fn email_result(matches: &[String], config: &Config, is_debug: bool, prevent_ema
     );
 
     html_body.push_str("<title>");
-    html_body.push_str(&subject);
+    html_body.push_str(&escape_html(subject));
     html_body.push_str("</title>");
     html_body.push_str("</head>");
     html_body.push_str("<body>");
     html_body.push_str(&format!("<h2><a class=\"url\" href=\"{}\">", url,));
-    html_body.push_str(&url.to_string());
-    html_body.push_str("</h2></a><br>");
-    html_body.push_str("<table class=\"container\"><tbody>");
-    html_body.push_str(
-        &matches
-            .iter()
-            .map(|match_body| format!("<td>{}<td>", match_body))
-            .collect::<Vec<String>>()
-            .join("<br/>"),
-    );
-    html_body.push_str("</tbody></table>");
+    html_body.push_str(url.as_str());
+    html_body.push_str("</h2></a>");
+    html_body.push_str(&format!("<p>{}</p>", escape_html(headline)));
+    if matches!(config.content_type, ContentType::Csv) {
+        html_body.push_str(&rows_table(matches));
+    } else {
+        html_body.push_str("<table class=\"container\"><tbody>");
+        html_body.push_str(
+            &matches
+                .iter()
+                .map(|result| {
+                    let content = if result.is_html {
+                        result.content.clone()
+                    } else {
+                        // keep the layout of text and JSON matches and stop them being read as HTML
+                        format!("<pre>{}</pre>", escape_html(&result.content))
+                    };
+                    format!("<td>{}{}<td>", content, fields_table(result))
+                })
+                .collect::<Vec<String>>()
+                .join("<br/>"),
+        );
+        html_body.push_str("</tbody></table>");
+    }
     html_body.push_str("</body>");
     html_body.push_str("</html>");
 
+    let (email_from, email_to) = email_addresses(config)?;
     let email = Message::builder()
-        .from(config.email_from.as_ref().unwrap().clone())
-        .to(config.email_to.as_ref().unwrap().clone())
+        .from(email_from)
+        .to(email_to)
         .subject(subject)
         .multipart(
             MultiPart::alternative()
                 .singlepart(
                     SinglePart::builder()
                         .header(header::ContentType::TEXT_PLAIN)
-                        .body(format!("Results:\n{}", matches.join("\n---\n"))),
+                        .body(format!(
+                            "{}\n\n{}",
+                            headline,
+                            matches
+                                .iter()
+                                .map(Match::to_string)
+                                .collect::<Vec<String>>()
+                                .join("\n---\n")
+                        )),
                 )
                 .singlepart(
                     SinglePart::builder()
@@ -352,318 +1022,133 @@ fn email_result(matches: &[String], config: &Config, is_debug: bool, prevent_ema
                         .body(html_body.clone()),
                 ),
         )
-        .unwrap();
+        .map_err(|e| GemError::Notify(format!("Unable to build email: {}", e)))?;
 
     if is_debug {
         println!("Email {}", html_body);
-        let mut f = File::create("tmp/email.html").unwrap();
-        f.write_all(html_body.as_bytes()).unwrap();
-        f.sync_data().unwrap();
+        write_debug_file("tmp/email.html", &html_body);
     }
 
     if !prevent_email {
-        send_email(&email);
+        send_email(&email)
     } else {
         println!("PREVENT_EMAIL set - not sending email\n{}\n", html_body);
+        Ok(())
     }
 }
 
-fn email_error(error: &str, config: &Config) {
-    let email = Message::builder()
-        .from(config.email_from.as_ref().unwrap().clone())
-        .to(config.email_to.as_ref().unwrap().clone())
-        .subject(format!("Error polling site {}", config.url))
-        .body(format!("Error: \n{}", error))
-        .unwrap();
-
-    send_email(&email);
-}
-
-fn send_email(email: &Message) {
-    let smtp_relay = env::var(SMTP_RELAY_KEY).expect("Need SMTP relay url");
-    let smtp_user = env::var(SMTP_USER_KEY).expect("Need SMTP username");
-    let smtp_pass = env::var(SMTP_PASS_KEY).expect("Need SMTP password");
-
-    let creds = Credentials::new(smtp_user, smtp_pass);
-
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::relay(&smtp_relay)
-        .unwrap()
-        .credentials(creds)
-        .build();
-
-    match mailer.send(&email) {
-        Ok(_) => println!("Email sent"),
-        Err(e) => panic!("Error sending email {}", e),
-    };
-}
-
-fn search_for_text(content: &str, config: &Config) -> Vec<String> {
-    let mut results = Vec::new();
-
-    let lines = content
-        .split('\n')
-        .map(|part| part.to_string())
-        .collect::<Vec<String>>();
+/// Lists any named fields of a match under its content.
+fn fields_table(result: &Match) -> String {
+    if result.fields.is_empty() {
+        return String::new();
+    }
 
-    config
-        .search_terms
-        .as_ref()
-        .unwrap()
+    let rows = result
+        .fields
         .iter()
-        .for_each(|term| {
-            for (index, line) in lines.iter().enumerate() {
-                if line.contains(term) {
-                    results.push(
-                        [
-                            lines[index - 1].clone(),
-                            line.clone(),
-                            lines[index + 1].clone(),
-                        ]
-                        .join("\n"),
-                    );
-                }
-            }
-        });
-
-    results
+        .map(|(name, value)| {
+            format!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape_html(name),
+                escape_html(value)
+            )
+        })
+        .collect::<String>();
+    format!("<table class=\"fields\">{}</table>", rows)
 }
 
-fn parse_html_and_search(content: &str, config: &Config) -> Vec<String> {
-    let document = Html::parse_document(content);
-    let selector = config
-        .selector
-        .as_ref()
-        .map(|selector| Selector::parse(selector).expect("Unable to parse selector"));
-
-    let mut results = Vec::new();
-    let origin = config.url.origin();
-
-    if let Some(selector) = selector {
-        for element in document.select(&selector) {
-            // assume is the url is relatively defined we use the origin of target
-            let mut element_html = element.html();
-            element_html = element_html.replace(
-                "href=\"/",
-                &format!("href=\"{}/", origin.ascii_serialization()),
-            );
-            element_html = element_html.replace("src=\"//", &format!("src=\"{}//", "https:"));
-
-            // todo handle scheme-less  //domain.com/image.png urls
-            let srcset_offset = element_html.find("srcset");
-            if let Some(offset) = srcset_offset {
-                let beg = offset + "srcset=\"".len();
-                let offset_end = element_html[beg..].find("\"").map(|i| beg + i).unwrap() + 1;
-                element_html.replace_range(offset..offset_end, "");
-            }
+/// One row per match with the columns across the top, for CSV rows.
+fn rows_table(matches: &[Match]) -> String {
+    let header = matches
+        .first()
+        .map(|first| {
+            first
+                .fields
+                .iter()
+                .map(|(name, _)| format!("<th>{}</th>", escape_html(name)))
+                .collect::<String>()
+        })
+        .unwrap_or_default();
+    let rows = matches
+        .iter()
+        .map(|result| {
+            let cells = result
+                .fields
+                .iter()
+                .map(|(_, value)| format!("<td>{}</td>", escape_html(value)))
+                .collect::<String>();
+            format!("<tr>{}</tr>", cells)
+        })
+        .collect::<String>();
 
-            results.push(element_html);
-        }
-    }
-    results
+    format!(
+        "<table class=\"rows\"><thead><tr>{}</tr></thead><tbody>{}</tbody></table>",
+        header, rows
+    )
 }
 
-async fn download_content(config: &Config, is_debug: bool) -> String {
-    let client = reqwest::Client::new();
-
-    // pretending to be google bot helps make sure we get a server-side rendered version of the app
-    let data = client.get(config.url.to_string()).header(USER_AGENT, "Mozilla/5.0 AppleWebKit/537.36 (KHTML, like Gecko; compatible; Googlebot/2.1; +http://www.google.com/bot.html) Chrome/W.X.Y.Z Safari/537.36").send().await;
-
-    if is_debug {
-        println!("{:?}", data);
-    }
-
-    if data.is_err() {
-        let error = data.unwrap_err();
-        email_error(&format!("Error fetching: {}", &error.to_string()), config);
-        panic!("Unable to fetch {}", error);
-    }
-
-    match data.unwrap().text().await {
-        Ok(body) => body,
-        Err(error) => {
-            email_error(
-                &format!("Error unwrapping body: {}", &error.to_string()),
-                config,
-            );
-            panic!("Error parsing {}", error);
-        }
+fn email_addresses(config: &Config) -> GemResult<(Mailbox, Mailbox)> {
+    match (&config.email_from, &config.email_to) {
+        (Some(email_from), Some(email_to)) => Ok((email_from.clone(), email_to.clone())),
+        _ => Err(GemError::Config(
+            "Need EMAIL_FROM and EMAIL_TO to send emails".to_string(),
+        )),
     }
 }
 
-#[derive(Builder, Clone)]
-struct Config {
-    content_type: ContentType,
-    url: Url,
-    search_terms: Option<Vec<String>>,
-    selector: Option<String>,
-
-    email_to: Option<Mailbox>,
-    email_from: Option<Mailbox>,
-
-    signal_url: Option<Url>,
-    signal_message: Option<String>,
-    signal_recipients: Vec<String>,
-    signal_sender: Option<String>,
+fn email_error(error: &str, config: &Config) -> GemResult<()> {
+    let (email_from, email_to) = email_addresses(config)?;
+    let email = Message::builder()
+        .from(email_from)
+        .to(email_to)
+        .subject(format!("Error polling site {}", config.url))
+        .body(format!("Error: \n{}", error))
+        .map_err(|e| GemError::Notify(format!("Unable to build error email: {}", e)))?;
 
-    notification_types: Vec<NotificationType>,
+    send_email(&email)
 }
 
-fn load_config() -> Config {
-    let url_string = env::var(TARGET_URL_KEY).expect("Please define TARGET_URL in .env");
-    let url = Url::parse(&url_string).expect("Invalid URL");
-    println!("Polling {} ", &url_string);
+fn send_email(email: &Message) -> GemResult<()> {
+    let smtp_relay = require_var(SMTP_RELAY_KEY, "Need SMTP relay url")?;
+    let smtp_user = require_var(SMTP_USER_KEY, "Need SMTP username")?;
+    let smtp_pass = require_var(SMTP_PASS_KEY, "Need SMTP password")?;
 
-    let content_type_string =
-        env::var(CONTENT_TYPE_KEY).expect("Please define CONTENT_TYPE in .env");
-    let content_type = ContentType::try_from(&content_type_string)
-        .unwrap_or_else(|_| panic!("Unknown content type {}", content_type_string));
+    let creds = Credentials::new(smtp_user, smtp_pass);
 
-    println!("for '{}' content", content_type);
+    // Open a remote connection to gmail
+    let mailer = SmtpTransport::relay(&smtp_relay)
+        .map_err(|e| GemError::Config(format!("Invalid SMTP relay {}: {}", smtp_relay, e)))?
+        .credentials(creds)
+        .build();
 
-    let (search_terms, selector) = match content_type {
-        ContentType::Html => {
-            let selector = env::var(SELECTOR_KEY)
-                .expect("Please supply SELECTOR in .env for HTML content type");
-            println!("using selector: {}", &selector);
-            (None, Some(selector))
-        }
-        ContentType::Text => {
-            let search_text = env::var(SEARCH_TEXT_KEY)
-                .expect("Please define SEARCH_TEXT in .env as comma separated entries");
-            println!("using search_text: {}", search_text);
-
-            let search_terms = search_text
-                .split(',')
-                .map(|term| term.to_owned())
-                .collect::<Vec<String>>();
-            (Some(search_terms), None)
+    match mailer.send(email) {
+        Ok(_) => {
+            println!("Email sent");
+            Ok(())
         }
-    };
-
-    let mut config_builder = ConfigBuilder::default();
-    config_builder
-        .search_terms(search_terms)
-        .selector(selector)
-        .content_type(content_type)
-        .email_from(None)
-        .email_to(None)
-        .url(url);
-
-    let notification_type_string = env::var(NOTIFICATION_TYPE_KEY)
-        .expect("Need NOTIFICATION_TYPE with comma separated types e.g signal,email");
-    let notification_types = notification_type_string
-        .split(",")
-        .map(|entry| NotificationType::try_from(entry).expect("Unknown NOTIFICATION_TYPE"))
-        .collect::<Vec<NotificationType>>();
-
-    if notification_types.contains(&NotificationType::Email) {
-        println!("Config for Email notifs found");
-
-        let email_to_string =
-            env::var(EMAIL_TO_KEY).expect("Need EMAIL_TO user email: 'User <user@example.com>'");
-        let email_to = email_to_string
-            .parse::<Mailbox>()
-            .expect("Need user of form: 'User <user@example.com>'");
-
-        let email_from_string = env::var(EMAIL_FROM_KEY)
-            .expect("Need EMAIL_FROM user email: 'App Name <app@example.com>'");
-        let email_from = email_from_string
-            .parse::<Mailbox>()
-            .expect("Need user of form: 'App Name <app@example.com>'");
-
-        // doing this upfront so we can exit early
-        let _ = env::var(SMTP_RELAY_KEY).expect("Need SMTP relay url");
-        let _ = env::var(SMTP_USER_KEY).expect("Need SMTP username");
-        let _ = env::var(SMTP_PASS_KEY).expect("Need SMTP password");
-
-        config_builder
-            .email_to(Some(email_to))
-            .email_from(Some(email_from));
-    }
-
-    if notification_types.contains(&NotificationType::Signal) {
-        println!("Config for Signal notifs found");
-
-        let signal_url_string = env::var(SIGNAL_URL_KEY).expect("Need SIGNAL_URL to send notifs");
-
-        let signal_url = Url::from_str(&signal_url_string).expect("Error parsing signal URL");
-
-        let signal_message_prefix = env::var(SIGNAL_MESSAGE_PREFIX_KEY).ok();
-
-        let signal_recipients = env::var(SIGNAL_RECIPIENTS_KEY)
-            .expect("Need SIGNAL_RECIPIENTS to send notifs")
-            .split(",")
-            .map(|val| val.to_string())
-            .collect::<Vec<String>>();
-
-        let signal_sender = env::var(SIGNAL_SENDER_KEY).expect("Need SIGNAL_SEND to send notifs");
-
-        config_builder
-            .signal_message(signal_message_prefix)
-            .signal_url(Some(signal_url))
-            .signal_recipients(signal_recipients)
-            .signal_sender(Some(signal_sender));
+        Err(e) => Err(GemError::Notify(format!("Error sending email {}", e))),
     }
-
-    config_builder
-        .notification_types(notification_types)
-        .build()
-        .expect("Unable to build config")
-}
-
-#[derive(Clone)]
-enum ContentType {
-    Html,
-    Text,
 }
 
-impl TryFrom<&String> for ContentType {
-    type Error = &'static str;
-
-    fn try_from(value: &String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().trim() {
-            "html" => Ok(ContentType::Html),
-            "text" => Ok(ContentType::Text),
-            _ => Err("Unknown content type"),
+/// Emails the error when fetching fails, passing the result on either way.
+fn reported<T>(result: GemResult<T>, config: &Config, store: &dyn StateStore) -> GemResult<T> {
+    if let Err(GemError::Fetch(message)) = &result {
+        if let Err(error) = report_fetch_error(message, config, store) {
+            eprintln!("Unable to email error: {}", error);
         }
     }
+    result
 }
 
-impl Display for ContentType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let content_type_string = match self {
-            ContentType::Html => "HTML",
-            ContentType::Text => "text",
-        };
-        f.write_fmt(format_args!("{content_type_string}"))
-    }
-}
-
-#[derive(Debug, PartialEq, Clone)]
-enum NotificationType {
-    Signal,
-    Email,
-}
-
-impl TryFrom<&str> for NotificationType {
-    type Error = &'static str;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value.to_lowercase().trim() {
-            "signal" => Ok(NotificationType::Signal),
-            "email" => Ok(NotificationType::Email),
-            _ => Err("Unknown notification type"),
-        }
+/// Emails fetch failures when email is set up for the target and its rate limit allows.
+fn report_fetch_error(message: &str, config: &Config, store: &dyn StateStore) -> GemResult<()> {
+    if !config.notification_types.contains(&NotificationType::Email) {
+        return Ok(());
     }
-}
 
-impl Display for NotificationType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let notification_type_string = match self {
-            NotificationType::Email => "Email",
-            NotificationType::Signal => "Signal",
-        };
-        f.write_fmt(format_args!("{}", notification_type_string))
-    }
+    let Some(token) = rate_limit::take_token(store, config, &NotificationType::Email)? else {
+        return Ok(());
+    };
+    email_error(message, config)?;
+    token.spend(store)
 }
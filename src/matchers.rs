@@ -0,0 +1,226 @@
+use std::fmt::Display;
+
+use regex::{Regex, RegexBuilder};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ContentType};
+use crate::error::{GemError, GemResult};
+
+mod csv_filter;
+mod json;
+mod xpath;
+
+pub use csv_filter::parse_filter;
+pub use json::compile_json_path;
+pub use xpath::compile_xpath;
+
+/// Something found in a target's content.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Match {
+    /// What was found, either HTML or plain text going by `is_html`
+    pub content: String,
+    /// Whether the content is HTML that can go straight into an email
+    #[serde(default)]
+    pub is_html: bool,
+    /// Named values found alongside the content, e.g. regex capture groups
+    pub fields: Vec<(String, String)>,
+    /// Identifies the same match across runs when its content can change, e.g. a JSON path
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+impl Match {
+    pub fn new(content: String) -> Match {
+        Match {
+            content,
+            is_html: false,
+            fields: Vec::new(),
+            key: None,
+        }
+    }
+
+    pub fn html(content: String) -> Match {
+        Match {
+            is_html: true,
+            ..Match::new(content)
+        }
+    }
+
+    /// What identifies this match between runs, the content itself when there's no better key
+    pub fn key(&self) -> &str {
+        self.key.as_deref().unwrap_or(&self.content)
+    }
+
+    /// The content as text on one line, cut short after `max_chars`
+    pub fn snippet(&self, max_chars: usize) -> String {
+        let text = match self.is_html {
+            true => Html::parse_fragment(&self.content)
+                .root_element()
+                .text()
+                .collect::<String>(),
+            false => self.content.clone(),
+        };
+        let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+        match text.char_indices().nth(max_chars) {
+            Some((end, _)) => format!("{}…", &text[..end]),
+            None => text,
+        }
+    }
+
+    /// The fields as `name=value` pairs on one line
+    pub fn fields_summary(&self) -> String {
+        self.fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.content)?;
+        for (name, value) in &self.fields {
+            f.write_fmt(format_args!("\n{}: {}", name, value))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn find_matches(content: &str, config: &Config) -> GemResult<Vec<Match>> {
+    match config.content_type {
+        ContentType::Html => parse_html_and_search(content, config),
+        ContentType::Text => Ok(search_for_text(content, config)),
+        ContentType::Regex => search_for_pattern(content, config),
+        ContentType::XPath => xpath::search_with_xpath(content, config),
+        ContentType::Json => json::search_with_json_path(content, config),
+        ContentType::Csv => csv_filter::search_csv(content, config),
+    }
+}
+
+/// Builds the regex for `PATTERN` with any `PATTERN_FLAGS` applied:
+/// `i` case-insensitive, `m` multiline, `s` `.` matches new lines and `x` verbose.
+pub fn compile_pattern(pattern: &str, flags: Option<&str>) -> Result<Regex, String> {
+    let mut builder = RegexBuilder::new(pattern);
+
+    for flag in flags.unwrap_or_default().chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => {
+                return Err(format!(
+                    "Unknown pattern flag '{}', expected i, m, s or x",
+                    flag
+                ))
+            }
+        };
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Unable to parse pattern '{}': {}", pattern, e))
+}
+
+fn search_for_pattern(content: &str, config: &Config) -> GemResult<Vec<Match>> {
+    let Some(pattern) = &config.pattern else {
+        return Ok(Vec::new());
+    };
+    let regex =
+        compile_pattern(pattern, config.pattern_flags.as_deref()).map_err(GemError::Parse)?;
+
+    let results = regex
+        .captures_iter(content)
+        .map(|captures| {
+            let mut found = Match::new(captures[0].to_string());
+            found.fields = regex
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    captures
+                        .name(name)
+                        .map(|value| (name.to_string(), value.as_str().to_string()))
+                })
+                .collect();
+            found
+        })
+        .collect();
+
+    Ok(results)
+}
+
+fn search_for_text(content: &str, config: &Config) -> Vec<Match> {
+    let mut results = Vec::new();
+
+    let lines = content
+        .split('\n')
+        .map(|part| part.to_string())
+        .collect::<Vec<String>>();
+
+    config.search_terms.iter().flatten().for_each(|term| {
+        for (index, line) in lines.iter().enumerate() {
+            if line.contains(term) {
+                // include the lines either side for context, where there are any
+                let context_start = index.saturating_sub(1);
+                let context_end = (index + 2).min(lines.len());
+                results.push(Match::new(lines[context_start..context_end].join("\n")));
+            }
+        }
+    });
+
+    results
+}
+
+fn parse_html_and_search(content: &str, config: &Config) -> GemResult<Vec<Match>> {
+    let document = Html::parse_document(content);
+    let selector = config
+        .selector
+        .as_ref()
+        .map(|selector| {
+            Selector::parse(selector).map_err(|e| {
+                GemError::Parse(format!("Unable to parse selector '{}': {:?}", selector, e))
+            })
+        })
+        .transpose()?;
+
+    let mut results = Vec::new();
+
+    if let Some(selector) = selector {
+        for element in document.select(&selector) {
+            results.push(Match::html(fix_urls(element.html(), config)));
+        }
+    }
+    Ok(results)
+}
+
+/// Points root relative links at the target so they still work from an email.
+fn fix_urls(mut element_html: String, config: &Config) -> String {
+    // assume is the url is relatively defined we use the origin of target
+    let origin = config.url.origin();
+    element_html = element_html.replace(
+        "href=\"/",
+        &format!("href=\"{}/", origin.ascii_serialization()),
+    );
+    element_html = element_html.replace("src=\"//", &format!("src=\"{}//", "https:"));
+
+    // todo handle scheme-less  //domain.com/image.png urls
+    let srcset_offset = element_html.find("srcset");
+    if let Some(offset) = srcset_offset {
+        let beg = offset + "srcset=\"".len();
+        if let Some(offset_end) = element_html[beg..].find('"').map(|i| beg + i + 1) {
+            element_html.replace_range(offset..offset_end, "");
+        }
+    }
+
+    element_html
+}
+
+/// Makes text safe to put between HTML tags.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,418 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use super::Match;
+use crate::config::Config;
+use crate::error::{GemError, GemResult};
+
+/// A parsed `CSV_FILTER`, e.g. `status == "in stock" && qty > 0`.
+///
+/// Comparisons are numeric when both sides are numbers and plain text otherwise. Columns are
+/// named by their header, wrapped in backticks when the name has spaces: `` `in stock` == "yes" ``.
+#[derive(Debug)]
+pub enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(Operand, Operator, Operand),
+}
+
+#[derive(Debug)]
+pub enum Operand {
+    Column(String),
+    Value(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Contains,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Column(String),
+    Value(String),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+pub fn parse_filter(filter: &str) -> Result<Filter, String> {
+    let tokens = tokenize(filter).map_err(|e| format!("Invalid CSV filter '{}': {}", filter, e))?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+    };
+
+    let parsed = parser
+        .or()
+        .map_err(|e| format!("Invalid CSV filter '{}': {}", filter, e))?;
+    if let Some(token) = parser.tokens.next() {
+        return Err(format!(
+            "Invalid CSV filter '{}': unexpected {:?}",
+            filter, token
+        ));
+    }
+
+    Ok(parsed)
+}
+
+/// Keeps the rows matching the target's filter, each one with its columns as fields and keyed by
+/// its first column.
+pub fn search_csv(content: &str, config: &Config) -> GemResult<Vec<Match>> {
+    let Some(filter) = &config.csv_filter else {
+        return Ok(Vec::new());
+    };
+    let filter = parse_filter(filter).map_err(GemError::Parse)?;
+    filter_rows(content, &filter)
+}
+
+fn filter_rows(content: &str, filter: &Filter) -> GemResult<Vec<Match>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| GemError::Parse(format!("Unable to read CSV header: {}", e)))?
+        .iter()
+        .map(|header| header.to_string())
+        .collect::<Vec<String>>();
+    if let Some(column) = filter
+        .columns()
+        .into_iter()
+        .find(|column| !headers.iter().any(|header| header == column))
+    {
+        return Err(GemError::Parse(format!("No CSV column named '{}'", column)));
+    }
+
+    let mut results = Vec::new();
+    for record in reader.records() {
+        let record =
+            record.map_err(|e| GemError::Parse(format!("Unable to read CSV row: {}", e)))?;
+        let fields = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, value)| (header.clone(), value.to_string()))
+            .collect::<Vec<(String, String)>>();
+
+        // a row too short to have a column the filter needs doesn't match it
+        if filter.matches(&fields) == Some(true) {
+            let mut found = Match::new(record.iter().collect::<Vec<&str>>().join(","));
+            // the first column is usually an id, e.g. a sku
+            found.key = record.get(0).map(|first| first.to_string());
+            found.fields = fields;
+            results.push(found);
+        }
+    }
+
+    Ok(results)
+}
+
+impl Filter {
+    /// `None` when the row is missing a column the filter looks at.
+    fn matches(&self, row: &[(String, String)]) -> Option<bool> {
+        match self {
+            Filter::Or(left, right) => Some(left.matches(row)? || right.matches(row)?),
+            Filter::And(left, right) => Some(left.matches(row)? && right.matches(row)?),
+            Filter::Not(inner) => Some(!inner.matches(row)?),
+            Filter::Compare(left, operator, right) => {
+                Some(operator.compare(left.resolve(row)?, right.resolve(row)?))
+            }
+        }
+    }
+
+    /// Every column the filter names.
+    fn columns(&self) -> Vec<&str> {
+        match self {
+            Filter::Or(left, right) | Filter::And(left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Filter::Not(inner) => inner.columns(),
+            Filter::Compare(left, _, right) => [left, right]
+                .into_iter()
+                .filter_map(|operand| match operand {
+                    Operand::Column(column) => Some(column.as_str()),
+                    Operand::Value(_) => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Operand {
+    fn resolve<'a>(&'a self, row: &'a [(String, String)]) -> Option<&'a str> {
+        match self {
+            Operand::Value(value) => Some(value),
+            Operand::Column(column) => row
+                .iter()
+                .find(|(header, _)| header == column)
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+}
+
+impl Operator {
+    fn compare(&self, left: &str, right: &str) -> bool {
+        if *self == Operator::Contains {
+            return left.contains(right);
+        }
+
+        let ordering = match (left.parse::<f64>(), right.parse::<f64>()) {
+            (Ok(left), Ok(right)) => left.partial_cmp(&right),
+            _ => Some(left.cmp(right)),
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+
+        match self {
+            Operator::Equal => ordering == Ordering::Equal,
+            Operator::NotEqual => ordering != Ordering::Equal,
+            Operator::Greater => ordering == Ordering::Greater,
+            Operator::GreaterOrEqual => ordering != Ordering::Less,
+            Operator::Less => ordering == Ordering::Less,
+            Operator::LessOrEqual => ordering != Ordering::Greater,
+            Operator::Contains => false,
+        }
+    }
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+
+    while let Some(&next) = chars.peek() {
+        match next {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if next == '(' {
+                    Token::Open
+                } else {
+                    Token::Close
+                });
+            }
+            '"' | '\'' | '`' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(c) if c == next => break,
+                        Some(c) => text.push(c),
+                        None => return Err(format!("unclosed {}", next)),
+                    }
+                }
+                tokens.push(if next == '`' {
+                    Token::Column(text)
+                } else {
+                    Token::Value(text)
+                });
+            }
+            '=' | '!' | '<' | '>' | '&' | '|' => {
+                chars.next();
+                let doubled = chars.next_if_eq(&'=').is_some();
+                let token = match (next, doubled) {
+                    ('=', true) => Token::Operator(Operator::Equal),
+                    ('!', true) => Token::Operator(Operator::NotEqual),
+                    ('!', false) => Token::Not,
+                    ('<', true) => Token::Operator(Operator::LessOrEqual),
+                    ('<', false) => Token::Operator(Operator::Less),
+                    ('>', true) => Token::Operator(Operator::GreaterOrEqual),
+                    ('>', false) => Token::Operator(Operator::Greater),
+                    ('&', false) if chars.next_if_eq(&'&').is_some() => Token::And,
+                    ('|', false) if chars.next_if_eq(&'|').is_some() => Token::Or,
+                    _ => return Err(format!("unexpected '{}'", next)),
+                };
+                tokens.push(token);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "_-.".contains(*c)) {
+                    word.push(c);
+                }
+                if word.is_empty() {
+                    return Err(format!("unexpected '{}'", next));
+                }
+
+                tokens.push(match word.as_str() {
+                    "contains" => Token::Operator(Operator::Contains),
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ if word.parse::<f64>().is_ok() => Token::Value(word),
+                    _ => Token::Column(word),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, `||` binds loosest then `&&` then `!`.
+struct Parser {
+    tokens: Peekable<IntoIter<Token>>,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut left = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            left = Filter::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut left = self.unary()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            left = Filter::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.tokens.next_if_eq(&Token::Not).is_some() {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+
+        if self.tokens.next_if_eq(&Token::Open).is_some() {
+            let inner = self.or()?;
+            return match self.tokens.next() {
+                Some(Token::Close) => Ok(inner),
+                _ => Err("missing ')'".to_string()),
+            };
+        }
+
+        let left = self.operand()?;
+        let operator = match self.tokens.next() {
+            Some(Token::Operator(operator)) => operator,
+            other => return Err(format!("expected a comparison, found {:?}", other)),
+        };
+        let right = self.operand()?;
+        Ok(Filter::Compare(left, operator, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.tokens.next() {
+            Some(Token::Column(column)) => Ok(Operand::Column(column)),
+            Some(Token::Value(value)) => Ok(Operand::Value(value)),
+            other => Err(format!("expected a column or value, found {:?}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOCK: &str = "sku,name,qty,price\n\
+                         a1,red shirt,0,9.5\n\
+                         b2,blue shirt,12,10\n\
+                         c3,\"green, large\",3,100\n\
+                         d4,short row\n";
+
+    fn skus(filter: &str) -> Vec<String> {
+        let filter = parse_filter(filter).unwrap();
+        filter_rows(STOCK, &filter)
+            .unwrap()
+            .into_iter()
+            .filter_map(|found| found.key)
+            .collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let filter = parse_filter("a == 1 || b == 2 && c == 3").unwrap();
+        assert!(matches!(
+            filter,
+            Filter::Or(_, right) if matches!(*right, Filter::And(_, _))
+        ));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let filter = parse_filter("!a == 1 && b == 2").unwrap();
+        assert!(matches!(
+            filter,
+            Filter::And(left, _) if matches!(*left, Filter::Not(_))
+        ));
+    }
+
+    #[test]
+    fn brackets_override_precedence() {
+        assert_eq!(skus("(qty > 0 || sku == 'a1') && price < 50"), ["a1", "b2"]);
+        assert_eq!(skus("qty > 0 || sku == 'a1' && price > 50"), ["b2", "c3"]);
+    }
+
+    #[test]
+    fn quoted_values_and_columns() {
+        let filter = parse_filter("`in stock` == 'a && b' || name contains \"(x)\"").unwrap();
+        let Filter::Or(left, right) = filter else {
+            panic!("expected ||");
+        };
+        assert!(matches!(
+            *left,
+            Filter::Compare(Operand::Column(ref column), Operator::Equal, Operand::Value(ref value))
+                if column == "in stock" && value == "a && b"
+        ));
+        assert!(matches!(
+            *right,
+            Filter::Compare(_, Operator::Contains, Operand::Value(ref value)) if value == "(x)"
+        ));
+
+        assert_eq!(skus("name == \"green, large\""), ["c3"]);
+    }
+
+    #[test]
+    fn rejects_bad_filters() {
+        assert!(parse_filter("name == 'open").is_err());
+        assert!(parse_filter("(qty > 0").is_err());
+        assert!(parse_filter("qty >").is_err());
+        assert!(parse_filter("qty > 0 qty").is_err());
+        assert!(parse_filter("qty = 0").is_err());
+    }
+
+    #[test]
+    fn compares_numbers_as_numbers() {
+        assert!(Operator::Greater.compare("10", "9"));
+        assert!(Operator::Equal.compare("10", "10.0"));
+        assert!(Operator::Less.compare("9.5", "10"));
+        assert_eq!(skus("price >= 10"), ["b2", "c3"]);
+    }
+
+    #[test]
+    fn compares_text_as_text() {
+        assert!(Operator::Less.compare("10", "9a"));
+        assert!(Operator::Greater.compare("b", "a"));
+        assert!(Operator::NotEqual.compare("Shirt", "shirt"));
+        assert!(Operator::Contains.compare("red shirt", "shirt"));
+    }
+
+    #[test]
+    fn short_rows_do_not_match() {
+        assert_eq!(skus("qty != 5"), ["a1", "b2", "c3"]);
+        assert_eq!(skus("!(qty == 5)"), ["a1", "b2", "c3"]);
+        assert_eq!(skus("name contains 'short'"), ["d4"]);
+    }
+
+    #[test]
+    fn unknown_columns_are_an_error() {
+        let filter = parse_filter("colour == red").unwrap();
+        assert!(filter_rows(STOCK, &filter).is_err());
+    }
+}
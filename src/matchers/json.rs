@@ -0,0 +1,36 @@
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+use super::Match;
+use crate::config::Config;
+use crate::error::{GemError, GemResult};
+
+pub fn compile_json_path(query: &str) -> Result<JsonPath, String> {
+    JsonPath::parse(query).map_err(|e| format!("Unable to parse JSONPath '{}': {}", query, e))
+}
+
+/// Runs the target's JSONPath query (RFC 9535, so filters like `$.variants[?(@.available==true)]`
+/// work) and returns each node it selects, pretty printed.
+pub fn search_with_json_path(content: &str, config: &Config) -> GemResult<Vec<Match>> {
+    let Some(query) = &config.json_path else {
+        return Ok(Vec::new());
+    };
+    let json_path = compile_json_path(query).map_err(GemError::Parse)?;
+    let value = serde_json::from_str::<Value>(content)
+        .map_err(|e| GemError::Parse(format!("Unable to parse JSON: {}", e)))?;
+
+    json_path
+        .query_located(&value)
+        .iter()
+        .map(|node| {
+            let content = serde_json::to_string_pretty(node.node())
+                .map_err(|e| GemError::Parse(format!("Unable to print JSON match: {}", e)))?;
+            let path = node.location().to_string();
+            let mut found = Match::new(content);
+            // where the node was found so two similar matches can be told apart
+            found.fields.push(("path".to_string(), path.clone()));
+            found.key = Some(path);
+            Ok(found)
+        })
+        .collect()
+}
@@ -0,0 +1,128 @@
+use scraper::{ElementRef, Html};
+use sxd_document::dom::Document;
+use sxd_document::{parser, Package};
+use sxd_xpath::nodeset::Node;
+use sxd_xpath::{Context, Factory, Value, XPath};
+
+use super::{escape_html, fix_urls, Match};
+use crate::config::Config;
+use crate::error::{GemError, GemResult};
+
+/// Elements that never have content so are written without a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+pub fn compile_xpath(expression: &str) -> Result<XPath, String> {
+    match Factory::new().build(expression) {
+        Ok(Some(xpath)) => Ok(xpath),
+        Ok(None) => Err("XPath is empty".to_string()),
+        Err(e) => Err(format!("Unable to parse XPath '{}': {}", expression, e)),
+    }
+}
+
+/// Runs the target's XPath against the content, which is read as XML when it starts with an
+/// `<?xml` declaration and as (possibly messy) HTML otherwise.
+pub fn search_with_xpath(content: &str, config: &Config) -> GemResult<Vec<Match>> {
+    let Some(expression) = &config.xpath else {
+        return Ok(Vec::new());
+    };
+    let xpath = compile_xpath(expression).map_err(GemError::Parse)?;
+
+    let package = if content.trim_start().starts_with("<?xml") {
+        parser::parse(content)
+            .map_err(|e| GemError::Parse(format!("Unable to parse XML: {:?}", e)))?
+    } else {
+        html_to_package(content)
+    };
+    let document = package.as_document();
+
+    let value = xpath
+        .evaluate(&Context::new(), document.root())
+        .map_err(|e| {
+            GemError::Parse(format!("Unable to evaluate XPath '{}': {}", expression, e))
+        })?;
+
+    let results = match value {
+        Value::Nodeset(nodes) => nodes
+            .document_order()
+            .into_iter()
+            .map(|node| match node {
+                Node::Element(_) => {
+                    let mut element_html = String::new();
+                    write_node(node, &mut element_html);
+                    Match::html(fix_urls(element_html, config))
+                }
+                _ => Match::new(node.string_value()),
+            })
+            .collect(),
+        // things like count() or boolean() give a single value, 0, "" and false mean nothing found
+        other if !other.boolean() => Vec::new(),
+        other => vec![Match::new(other.into_string())],
+    };
+
+    Ok(results)
+}
+
+/// html5ever copes with pages that aren't well formed XML so parse with that, then copy the
+/// tree over for sxd to query.
+fn html_to_package(content: &str) -> Package {
+    let html = Html::parse_document(content);
+    let package = Package::new();
+    {
+        let document = package.as_document();
+        let root = copy_element(&document, html.root_element());
+        document.root().append_child(root);
+    }
+    package
+}
+
+fn copy_element<'d>(document: &Document<'d>, source: ElementRef) -> sxd_document::dom::Element<'d> {
+    let element = document.create_element(source.value().name());
+    for (name, value) in source.value().attrs() {
+        element.set_attribute_value(name, value);
+    }
+
+    for child in source.children() {
+        if let Some(child_element) = ElementRef::wrap(child) {
+            element.append_child(copy_element(document, child_element));
+        } else if let scraper::Node::Text(text) = child.value() {
+            element.append_child(document.create_text(text));
+        }
+    }
+
+    element
+}
+
+fn write_node(node: Node, out: &mut String) {
+    match node {
+        Node::Element(element) => {
+            let name = node.prefixed_name().unwrap_or_default();
+            out.push('<');
+            out.push_str(&name);
+            for attribute in element.attributes() {
+                let attribute_name = Node::Attribute(attribute)
+                    .prefixed_name()
+                    .unwrap_or_default();
+                out.push_str(&format!(
+                    " {}=\"{}\"",
+                    attribute_name,
+                    escape_html(attribute.value()).replace('"', "&quot;")
+                ));
+            }
+            out.push('>');
+
+            let children = node.children();
+            if children.is_empty() && VOID_ELEMENTS.contains(&name.as_str()) {
+                return;
+            }
+            for child in children {
+                write_node(child, out);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        Node::Text(text) => out.push_str(&escape_html(text.text())),
+        _ => {}
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use crate::config::{Config, NotificationType};
+use crate::error::GemResult;
+use crate::matchers::Match;
+use crate::state::{HeldNotifications, StateStore};
+
+/// A daily window when a channel holds its notifications, written as `HH:MM-HH:MM` with an
+/// optional IANA timezone after it e.g. `22:00-07:00 Europe/London`. UTC is used without one.
+///
+/// The window may wrap past midnight. Anything found during it is sent as one summary on the
+/// first check after it ends.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+}
+
+impl QuietHours {
+    /// Whether `now` falls in the window, going by when its latest start and following end
+    /// actually happened rather than the clock on the wall, which repeats an hour when daylight
+    /// saving ends.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let today = now.with_timezone(&self.timezone).date_naive();
+        let mut start = self.at(today, self.start);
+        if start > now {
+            start = self.at(today.pred_opt().unwrap_or(today), self.start);
+        }
+
+        now < self.ends_at(start)
+    }
+
+    /// The next time the window ends after `now`
+    pub fn ends_at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.with_timezone(&self.timezone).date_naive();
+        let end = self.at(today, self.end);
+        if end > now {
+            return end;
+        }
+
+        self.at(today.succ_opt().unwrap_or(today), self.end)
+    }
+
+    /// The first moment on `date` that the local clock reads `time` or later. A time skipped by
+    /// daylight saving comes when the clocks jump past it, and one that happens twice comes the
+    /// first time round.
+    fn at(&self, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+        let local = date.and_time(time);
+        (0..=24 * 60)
+            .find_map(|minutes| {
+                self.timezone
+                    .from_local_datetime(&(local + Duration::minutes(minutes)))
+                    .earliest()
+            })
+            .map_or(Utc.from_utc_datetime(&local), |at| at.with_timezone(&Utc))
+    }
+}
+
+impl TryFrom<&str> for QuietHours {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "Invalid quiet hours '{}', expected HH:MM-HH:MM with an optional timezone e.g. 22:00-07:00 Europe/London",
+                value.trim()
+            )
+        };
+
+        let mut parts = value.split_whitespace();
+        let (start, end) = parts
+            .next()
+            .and_then(|window| window.split_once('-'))
+            .ok_or_else(invalid)?;
+        let start = NaiveTime::parse_from_str(start, "%H:%M").map_err(|_| invalid())?;
+        let end = NaiveTime::parse_from_str(end, "%H:%M").map_err(|_| invalid())?;
+        if start == end {
+            return Err(format!(
+                "Quiet hours '{}' start and end at the same time",
+                value.trim()
+            ));
+        }
+
+        let timezone = match parts.next() {
+            Some(name) => name
+                .parse::<Tz>()
+                .map_err(|_| format!("Unknown timezone '{}' in quiet hours", name))?,
+            None => Tz::UTC,
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(QuietHours {
+            start,
+            end,
+            timezone,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        QuietHours::try_from(value.as_str())
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{}-{} {}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M"),
+            self.timezone
+        ))
+    }
+}
+
+/// Queues the notification when `channel` is in its quiet hours, returning whether it was held.
+pub fn hold_if_quiet(
+    store: &dyn StateStore,
+    config: &Config,
+    channel: &NotificationType,
+    headline: &str,
+    matches: &[Match],
+) -> GemResult<bool> {
+    let now = Utc::now();
+    let Some(quiet_hours) = config
+        .quiet_hours(channel)
+        .filter(|quiet_hours| quiet_hours.contains(now))
+    else {
+        return Ok(false);
+    };
+
+    let target_id = config.id();
+    let mut held = store
+        .held(&target_id, channel)?
+        .unwrap_or_else(|| HeldNotifications::new(now));
+    held.add(headline, matches);
+    store.save_held(&target_id, channel, Some(&held))?;
+
+    println!(
+        "{}: holding {} notif during quiet hours {} until {}, {} held",
+        config.label(),
+        channel,
+        quiet_hours,
+        quiet_hours
+            .ends_at(now)
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        held.held
+    );
+    Ok(true)
+}
+
+/// Notifications held for `channel` that are due now its quiet hours are over.
+pub fn due_summary(
+    store: &dyn StateStore,
+    config: &Config,
+    channel: &NotificationType,
+) -> GemResult<Option<HeldNotifications>> {
+    let is_quiet = config
+        .quiet_hours(channel)
+        .is_some_and(|quiet_hours| quiet_hours.contains(Utc::now()));
+    if is_quiet {
+        return Ok(None);
+    }
+
+    store.held(&config.id(), channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn wraps_past_midnight() {
+        let quiet_hours = QuietHours::try_from("22:00-07:00").unwrap();
+        assert!(quiet_hours.contains(utc("2026-06-01T22:00:00Z")));
+        assert!(quiet_hours.contains(utc("2026-06-01T23:30:00Z")));
+        assert!(quiet_hours.contains(utc("2026-06-02T03:00:00Z")));
+        assert!(!quiet_hours.contains(utc("2026-06-02T07:00:00Z")));
+        assert!(!quiet_hours.contains(utc("2026-06-02T12:00:00Z")));
+        assert!(!quiet_hours.contains(utc("2026-06-02T21:59:00Z")));
+
+        let ends_at = utc("2026-06-02T07:00:00Z");
+        assert_eq!(quiet_hours.ends_at(utc("2026-06-01T23:30:00Z")), ends_at);
+        assert_eq!(quiet_hours.ends_at(utc("2026-06-02T03:00:00Z")), ends_at);
+    }
+
+    #[test]
+    fn keeps_to_the_same_day() {
+        let quiet_hours = QuietHours::try_from("09:00-17:00").unwrap();
+        assert!(!quiet_hours.contains(utc("2026-06-01T08:59:00Z")));
+        assert!(quiet_hours.contains(utc("2026-06-01T09:00:00Z")));
+        assert!(!quiet_hours.contains(utc("2026-06-01T17:00:00Z")));
+        assert_eq!(
+            quiet_hours.ends_at(utc("2026-06-01T18:00:00Z")),
+            utc("2026-06-02T17:00:00Z")
+        );
+    }
+
+    #[test]
+    fn goes_by_local_time() {
+        let quiet_hours = QuietHours::try_from("22:00-07:00 Europe/London").unwrap();
+        // 22:30 in summer time
+        assert!(quiet_hours.contains(utc("2026-06-01T21:30:00Z")));
+        assert!(!quiet_hours.contains(utc("2026-06-02T06:30:00Z")));
+        assert_eq!(
+            quiet_hours.ends_at(utc("2026-06-01T21:30:00Z")),
+            utc("2026-06-02T06:00:00Z")
+        );
+    }
+
+    #[test]
+    fn ends_when_the_clocks_skip_past_the_end() {
+        // the clocks in London go from 01:00 straight to 02:00 on 29 March 2026
+        let quiet_hours = QuietHours::try_from("22:00-01:30 Europe/London").unwrap();
+        let ends_at = utc("2026-03-29T01:00:00Z");
+        assert_eq!(quiet_hours.ends_at(utc("2026-03-28T23:00:00Z")), ends_at);
+        assert!(quiet_hours.contains(utc("2026-03-29T00:59:00Z")));
+        assert!(!quiet_hours.contains(ends_at));
+    }
+
+    #[test]
+    fn ends_the_first_time_a_repeated_end_comes_round() {
+        // the clocks in London go from 02:00 back to 01:00 on 25 October 2026
+        let quiet_hours = QuietHours::try_from("22:00-01:30 Europe/London").unwrap();
+        let ends_at = utc("2026-10-25T00:30:00Z");
+        assert_eq!(quiet_hours.ends_at(utc("2026-10-24T23:00:00Z")), ends_at);
+        assert!(quiet_hours.contains(utc("2026-10-25T00:29:00Z")));
+        assert!(!quiet_hours.contains(ends_at));
+        // 01:10 again, the window doesn't start over
+        assert!(!quiet_hours.contains(utc("2026-10-25T01:10:00Z")));
+        assert_eq!(
+            quiet_hours.ends_at(utc("2026-10-25T01:10:00Z")),
+            utc("2026-10-26T01:30:00Z")
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+use crate::config::{
+    env_var, Config, NotificationType, NOTIFICATION_INTERVAL_S_KEY,
+    NOTIFICATION_MAX_PER_INTERVAL_KEY,
+};
+use crate::error::{GemError, GemResult};
+use crate::state::{SendHistory, StateStore};
+
+const DEFAULT_NOTIFICATION_INTERVAL: u64 = 60 * 5; //5 minutes
+const DEFAULT_MAX_SEND: u32 = 3;
+
+/// How often a channel may notify for a target, written as `<max>/<period>` e.g. `1/1h` or
+/// `10/30m`, or `unlimited`.
+///
+/// Each channel has a bucket of `max` tokens that refills evenly over the period, so `3/15m`
+/// allows a burst of 3 then one more every 5 minutes.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RateLimit {
+    Unlimited,
+    Bucket { max: u32, period_s: u64 },
+}
+
+impl RateLimit {
+    /// `NOTIFICATION_MAX_PER_INTERVAL` per `NOTIFICATION_INTERVAL_S`, for channels without a limit
+    /// of their own.
+    pub fn from_env() -> GemResult<RateLimit> {
+        let period_s = env_var(NOTIFICATION_INTERVAL_S_KEY)?.map_or(
+            Ok(DEFAULT_NOTIFICATION_INTERVAL),
+            |val| {
+                val.parse::<u64>().map_err(|_| {
+                    GemError::Config(format!("Invalid number for notif interval {}", val))
+                })
+            },
+        )?;
+        let max =
+            env_var(NOTIFICATION_MAX_PER_INTERVAL_KEY)?.map_or(Ok(DEFAULT_MAX_SEND), |val| {
+                val.parse::<u32>()
+                    .ok()
+                    .filter(|max| *max > 0)
+                    .ok_or_else(|| {
+                        GemError::Config(format!("Invalid number for max notif {}", val))
+                    })
+            })?;
+
+        Ok(RateLimit::Bucket { max, period_s })
+    }
+
+    /// Takes a token for a send at `now`, giving back the new history or, when the bucket is
+    /// empty, the time the next token arrives.
+    fn take(
+        &self,
+        history: Option<&SendHistory>,
+        now: DateTime<Utc>,
+    ) -> Result<SendHistory, DateTime<Utc>> {
+        let RateLimit::Bucket { max, period_s } = *self else {
+            return Ok(SendHistory {
+                last_sent: now,
+                tokens: 0.0,
+            });
+        };
+        let capacity = f64::from(max);
+        let refill_per_s = capacity / period_s.max(1) as f64;
+        let tokens = history.map_or(capacity, |history| {
+            let elapsed_s = (now - history.last_sent).num_milliseconds().max(0) as f64 / 1000.0;
+            (history.tokens + elapsed_s * refill_per_s).min(capacity)
+        });
+
+        if tokens >= 1.0 {
+            Ok(SendHistory {
+                last_sent: now,
+                tokens: tokens - 1.0,
+            })
+        } else {
+            let wait_ms = ((1.0 - tokens) / refill_per_s * 1000.0).ceil() as i64;
+            Err(now + Duration::milliseconds(wait_ms))
+        }
+    }
+}
+
+impl TryFrom<&str> for RateLimit {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let value = value.trim().to_lowercase();
+        if value == "unlimited" {
+            return Ok(RateLimit::Unlimited);
+        }
+
+        let invalid = || {
+            format!(
+                "Invalid rate limit '{}', expected <max>/<period> e.g. 1/1h, or unlimited",
+                value
+            )
+        };
+        let (max, period) = value.split_once('/').ok_or_else(invalid)?;
+        let max = max
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|max| *max > 0)
+            .ok_or_else(invalid)?;
+
+        let period = period.trim();
+        let (number, multiplier) = match period.chars().last() {
+            Some('s') => (&period[..period.len() - 1], 1),
+            Some('m') => (&period[..period.len() - 1], 60),
+            Some('h') => (&period[..period.len() - 1], 60 * 60),
+            Some('d') => (&period[..period.len() - 1], 60 * 60 * 24),
+            _ => (period, 1),
+        };
+        let period_s = number.parse::<u64>().map_err(|_| invalid())? * multiplier;
+
+        Ok(RateLimit::Bucket { max, period_s })
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        RateLimit::try_from(value.as_str())
+    }
+}
+
+impl Display for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimit::Unlimited => f.write_str("unlimited"),
+            RateLimit::Bucket { max, period_s } => {
+                f.write_fmt(format_args!("{}/{}s", max, period_s))
+            }
+        }
+    }
+}
+
+/// A send the rate limit allows. Its token is only used up once the send has gone out, so a
+/// channel that fails doesn't run its bucket dry.
+pub struct SendToken {
+    target_id: String,
+    pub channel: NotificationType,
+    history: SendHistory,
+}
+
+impl SendToken {
+    pub fn spend(self, store: &dyn StateStore) -> GemResult<()> {
+        store.save_send_history(&self.target_id, &self.channel, &self.history)
+    }
+}
+
+/// A token for `channel` to notify for the target now, or `None` when its bucket is empty.
+pub fn take_token(
+    store: &dyn StateStore,
+    config: &Config,
+    channel: &NotificationType,
+) -> GemResult<Option<SendToken>> {
+    let rate_limit = match config.rate_limit(channel) {
+        Some(rate_limit) => *rate_limit,
+        None => RateLimit::from_env()?,
+    };
+    let target_id = config.id();
+    let history = store.send_history(&target_id, channel)?;
+
+    match rate_limit.take(history.as_ref(), Utc::now()) {
+        Ok(history) => Ok(Some(SendToken {
+            target_id,
+            channel: channel.clone(),
+            history,
+        })),
+        Err(next_eligible) => {
+            println!(
+                "{}: not sending {} notif, rate limit {} reached, next allowed at {}",
+                config.label(),
+                channel,
+                rate_limit,
+                next_eligible.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            );
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_limits() {
+        let bucket = |max, period_s| RateLimit::Bucket { max, period_s };
+        assert_eq!(RateLimit::try_from("1/1h"), Ok(bucket(1, 60 * 60)));
+        assert_eq!(RateLimit::try_from(" 10/30m "), Ok(bucket(10, 30 * 60)));
+        assert_eq!(RateLimit::try_from("3/2d"), Ok(bucket(3, 2 * 60 * 60 * 24)));
+        assert_eq!(RateLimit::try_from("5/90s"), Ok(bucket(5, 90)));
+        assert_eq!(RateLimit::try_from("5/90"), Ok(bucket(5, 90)));
+        assert_eq!(RateLimit::try_from("Unlimited"), Ok(RateLimit::Unlimited));
+    }
+
+    #[test]
+    fn rejects_bad_limits() {
+        for limit in [
+            "", "1", "0/1h", "-1/1h", "x/1h", "1/", "1/h", "1/xh", "1/1w",
+        ] {
+            assert!(RateLimit::try_from(limit).is_err(), "{}", limit);
+        }
+    }
+
+    #[test]
+    fn allows_a_burst_then_waits_for_the_refill() {
+        let limit = RateLimit::Bucket {
+            max: 3,
+            period_s: 15 * 60,
+        };
+
+        let mut history = None;
+        for _ in 0..3 {
+            history = Some(limit.take(history.as_ref(), at(0)).unwrap());
+        }
+        // one token comes back every 5 minutes
+        assert_eq!(limit.take(history.as_ref(), at(0)).unwrap_err(), at(5 * 60));
+        assert_eq!(
+            limit.take(history.as_ref(), at(60)).unwrap_err(),
+            at(5 * 60)
+        );
+
+        let history = limit.take(history.as_ref(), at(5 * 60)).unwrap();
+        assert!(limit.take(Some(&history), at(5 * 60)).is_err());
+    }
+
+    #[test]
+    fn refills_no_more_than_the_max() {
+        let limit = RateLimit::Bucket {
+            max: 2,
+            period_s: 60,
+        };
+
+        let history = limit.take(None, at(0)).unwrap();
+        let history = limit.take(Some(&history), at(60 * 60)).unwrap();
+        assert_eq!(history.tokens, 1.0);
+    }
+
+    #[test]
+    fn unlimited_always_allows() {
+        let history = RateLimit::Unlimited.take(None, at(0)).unwrap();
+        assert!(RateLimit::Unlimited.take(Some(&history), at(0)).is_ok());
+    }
+}
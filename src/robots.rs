@@ -0,0 +1,250 @@
+use reqwest::Url;
+
+/// The rules from a site's robots.txt, following RFC 9309. Only `user-agent`, `allow` and
+/// `disallow` are used, anything else such as `sitemap` is skipped.
+#[derive(Default)]
+pub struct RobotsTxt {
+    groups: Vec<Group>,
+}
+
+/// The rules under one or more `user-agent` lines.
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    is_allow: bool,
+    pattern: String,
+}
+
+impl RobotsTxt {
+    pub fn parse(content: &str) -> RobotsTxt {
+        let mut groups: Vec<Group> = Vec::new();
+        // agents listed one after the other share the rules that follow them, until a rule line
+        // ends the list, even an empty one
+        let mut is_listing_agents = false;
+
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    if !is_listing_agents {
+                        groups.push(Group::default());
+                        is_listing_agents = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        // only the product token counts, e.g. `Googlebot` from `Googlebot/2.1`
+                        let agent = value.split('/').next().unwrap_or_default();
+                        group.agents.push(agent.trim().to_lowercase());
+                    }
+                }
+                key @ ("allow" | "disallow") => {
+                    is_listing_agents = false;
+                    let Some(group) = groups.last_mut() else {
+                        continue;
+                    };
+                    // an empty disallow allows everything, which is the default anyway
+                    if !value.is_empty() {
+                        group.rules.push(Rule {
+                            is_allow: key == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        RobotsTxt { groups }
+    }
+
+    /// Whether `user_agent` may fetch `url`. The most specific group naming one of the agent's
+    /// product tokens applies, or the `*` group when none does, then the longest matching rule
+    /// wins with allow winning a tie.
+    pub fn is_allowed(&self, user_agent: &str, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        let tokens = product_tokens(user_agent);
+        let named = self
+            .groups
+            .iter()
+            .flat_map(|group| &group.agents)
+            .filter(|agent| tokens.contains(agent))
+            .max_by_key(|agent| agent.len());
+        let agent = named.map_or("*", String::as_str);
+
+        let rule = self
+            .groups
+            .iter()
+            .filter(|group| group.agents.iter().any(|name| name == agent))
+            .flat_map(|group| &group.rules)
+            .filter(|rule| matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.is_allow));
+        match rule {
+            Some(rule) => rule.is_allow,
+            None => true,
+        }
+    }
+}
+
+/// The names in a user agent that robots.txt groups go by, lowercased. That's its first word and
+/// any other word followed by a version, e.g. `mozilla`, `googlebot` and `chrome` from
+/// `Mozilla/5.0 (compatible; Googlebot/2.1) Chrome/120.0`.
+fn product_tokens(user_agent: &str) -> Vec<String> {
+    let is_token = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+
+    user_agent
+        .split(|c: char| c.is_whitespace() || "();,".contains(c))
+        .filter(|item| !item.is_empty())
+        .enumerate()
+        .filter_map(|(index, item)| match item.split_once('/') {
+            Some((name, _)) => Some(name),
+            None => (index == 0).then_some(item),
+        })
+        .filter(|name| is_token(name))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether a rule's path pattern matches the start of `path`, `*` matching any run of characters
+/// and a trailing `$` anchoring it to the end.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, is_anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = path.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<&str>>();
+    for (index, part) in parts.iter().enumerate() {
+        if is_anchored && index == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+
+    !is_anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOGLEBOT: &str = "Mozilla/5.0 AppleWebKit/537.36 (KHTML, like Gecko; compatible; \
+                             Googlebot/2.1; +http://www.google.com/bot.html) Chrome/W.X.Y.Z \
+                             Safari/537.36";
+
+    fn is_allowed(robots: &str, user_agent: &str, path: &str) -> bool {
+        let url = Url::parse("https://example.com")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        RobotsTxt::parse(robots).is_allowed(user_agent, &url)
+    }
+
+    #[test]
+    fn agents_in_a_row_share_a_group() {
+        let robots = "User-agent: gem\nUser-agent: otherbot\nDisallow: /private\n";
+        assert!(!is_allowed(robots, "gem/1.0", "/private"));
+        assert!(!is_allowed(robots, "otherbot", "/private"));
+        assert!(is_allowed(robots, "thirdbot", "/private"));
+    }
+
+    #[test]
+    fn empty_disallow_ends_a_group() {
+        let robots = "User-agent: gem\nDisallow:\n\nUser-agent: otherbot\nDisallow: /\n";
+        assert!(is_allowed(robots, "gem", "/page"));
+        assert!(!is_allowed(robots, "otherbot", "/page"));
+    }
+
+    #[test]
+    fn groups_for_the_same_agent_are_combined() {
+        let robots = "User-agent: gem\nDisallow: /a\n\nUser-agent: *\nDisallow: /\n\n\
+                      User-agent: gem\nDisallow: /b\n";
+        assert!(!is_allowed(robots, "gem", "/a"));
+        assert!(!is_allowed(robots, "gem", "/b"));
+        assert!(is_allowed(robots, "gem", "/c"));
+    }
+
+    #[test]
+    fn falls_back_to_the_star_group() {
+        let robots = "User-agent: otherbot\nDisallow: /\n\nUser-agent: *\nDisallow: /private\n";
+        assert!(is_allowed(robots, "gem", "/page"));
+        assert!(!is_allowed(robots, "gem", "/private/page"));
+        assert!(is_allowed(robots, "gem", "/robots.txt"));
+    }
+
+    #[test]
+    fn matches_on_product_tokens() {
+        let robots = "User-agent: Googlebot/2.1\nDisallow: /google\n\n\
+                      User-agent: bot\nDisallow: /bot\n\nUser-agent: *\nDisallow: /\n";
+        assert!(!is_allowed(robots, GOOGLEBOT, "/google"));
+        // `bot` is only part of a name so the googlebot group applies
+        assert!(is_allowed(robots, GOOGLEBOT, "/bot"));
+        assert!(!is_allowed(robots, "gembot/1.0", "/page"));
+        assert!(!is_allowed(robots, "bot/1.0", "/bot"));
+        assert!(is_allowed(robots, "bot/1.0", "/page"));
+    }
+
+    #[test]
+    fn longest_rule_wins_and_allow_wins_a_tie() {
+        let robots =
+            "User-agent: *\nDisallow: /shop\nAllow: /shop/items\nDisallow: /a\nAllow: /a\n";
+        assert!(!is_allowed(robots, "gem", "/shop/cart"));
+        assert!(is_allowed(robots, "gem", "/shop/items/1"));
+        assert!(is_allowed(robots, "gem", "/a"));
+    }
+
+    #[test]
+    fn product_tokens_skip_versions_and_comments() {
+        assert_eq!(
+            product_tokens(GOOGLEBOT),
+            ["mozilla", "applewebkit", "googlebot", "chrome", "safari"]
+        );
+        assert_eq!(product_tokens("gem"), ["gem"]);
+    }
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        assert!(matches("/*.pdf", "/files/report.pdf"));
+        assert!(matches("/*.pdf", "/files/report.pdf?download=1"));
+        assert!(matches("/shop/*/items", "/shop/a/b/items/1"));
+        assert!(!matches("/shop/*/items", "/shop/items"));
+        assert!(matches("/*", "/"));
+        assert!(matches("/private", "/private-notes"));
+        assert!(!matches("/private", "/public"));
+    }
+
+    #[test]
+    fn dollar_anchors_to_the_end() {
+        assert!(matches("/*.pdf$", "/files/report.pdf"));
+        assert!(!matches("/*.pdf$", "/files/report.pdf?download=1"));
+        assert!(matches("/page$", "/page"));
+        assert!(!matches("/page$", "/page/2"));
+        assert!(matches("/*.pdf$", "/a.pdf.pdf"));
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::changes::Fingerprint;
+use crate::config::{
+    env_var, notification_write_dir, NotificationType, STATE_DB_KEY, STATE_STORE_KEY,
+};
+use crate::error::{GemError, GemResult};
+use crate::matchers::Match;
+
+mod file;
+mod sqlite;
+
+/// How many runs are kept per target
+pub const MAX_RUNS: usize = 100;
+
+/// Notifications sent on one channel for one target, used for rate limiting.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SendHistory {
+    pub last_sent: DateTime<Utc>,
+    /// Sends left in the rate limit's bucket straight after the last one
+    pub tokens: f64,
+}
+
+/// The outcome of checking a target once.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunResult {
+    pub at: DateTime<Utc>,
+    pub match_count: usize,
+    /// Set when the fetch or match failed
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl RunResult {
+    pub fn succeeded(match_count: usize) -> RunResult {
+        RunResult {
+            at: Utc::now(),
+            match_count,
+            error: None,
+        }
+    }
+
+    pub fn failed(error: &GemError) -> RunResult {
+        RunResult {
+            at: Utc::now(),
+            match_count: 0,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Notifications a channel held back during its quiet hours, sent as one summary afterwards.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HeldNotifications {
+    /// How many notifications were held
+    pub held: u32,
+    pub since: DateTime<Utc>,
+    pub last_headline: String,
+    /// Every match held, the latest one kept for each `Match::key`
+    pub matches: Vec<Match>,
+}
+
+impl HeldNotifications {
+    pub fn new(since: DateTime<Utc>) -> HeldNotifications {
+        HeldNotifications {
+            held: 0,
+            since,
+            last_headline: String::new(),
+            matches: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, headline: &str, matches: &[Match]) {
+        self.held += 1;
+        self.last_headline = headline.to_string();
+        for found in matches {
+            match self
+                .matches
+                .iter_mut()
+                .find(|held| held.key() == found.key())
+            {
+                Some(held) => *held = found.clone(),
+                None => self.matches.push(found.clone()),
+            }
+        }
+    }
+
+    pub fn headline(&self, label: &str) -> String {
+        format!(
+            "Quiet hours summary for {}: {} notification(s) held since {}, latest: {}",
+            label,
+            self.held,
+            self.since
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            self.last_headline
+        )
+    }
+}
+
+/// What's needed to tell whether a target's page changed since it was last dealt with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PageCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Hex SHA-256 of the body, for servers that don't support conditional requests
+    pub hash: String,
+}
+
+/// Everything remembered between runs, keyed by `Config::id` and, for sends, the channel.
+pub trait StateStore: Send + Sync {
+    fn send_history(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+    ) -> GemResult<Option<SendHistory>>;
+    fn save_send_history(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+        history: &SendHistory,
+    ) -> GemResult<()>;
+
+    /// The matches as they were last notified about
+    fn fingerprints(&self, target_id: &str) -> GemResult<Vec<Fingerprint>>;
+    fn save_fingerprints(&self, target_id: &str, fingerprints: &[Fingerprint]) -> GemResult<()>;
+
+    /// Notifications held during the channel's quiet hours
+    fn held(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+    ) -> GemResult<Option<HeldNotifications>>;
+    /// Replaces the held notifications, `None` clears them once the summary is sent
+    fn save_held(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+        held: Option<&HeldNotifications>,
+    ) -> GemResult<()>;
+
+    /// When the target started matching (or, in absent mode, stopped), `None` while it isn't
+    fn triggered_since(&self, target_id: &str) -> GemResult<Option<DateTime<Utc>>>;
+    fn save_triggered_since(&self, target_id: &str, since: Option<DateTime<Utc>>) -> GemResult<()>;
+
+    /// Validators and hash of the target's page, only kept with `skip_unchanged`
+    fn page_cache(&self, target_id: &str) -> GemResult<Option<PageCache>>;
+    fn save_page_cache(&self, target_id: &str, page_cache: &PageCache) -> GemResult<()>;
+
+    /// Cookie jar saved after logging in, as JSON, only kept with `login_url`
+    fn session(&self, target_id: &str) -> GemResult<Option<String>>;
+    fn save_session(&self, target_id: &str, cookies: &str) -> GemResult<()>;
+
+    /// Up to `limit` of the latest runs, newest first
+    fn recent_runs(&self, target_id: &str, limit: usize) -> GemResult<Vec<RunResult>>;
+    /// Adds a run, dropping the oldest beyond `MAX_RUNS`
+    fn record_run(&self, target_id: &str, run: &RunResult) -> GemResult<()>;
+}
+
+/// Where state is kept, picked with `STATE_STORE`.
+pub enum StoreKind {
+    File,
+    Sqlite,
+}
+
+impl StoreKind {
+    /// `STATE_STORE`, `file` when unset.
+    pub fn from_env() -> GemResult<StoreKind> {
+        match env_var(STATE_STORE_KEY)?.as_deref().map(str::trim) {
+            None | Some("file") => Ok(StoreKind::File),
+            Some("sqlite") => Ok(StoreKind::Sqlite),
+            Some(other) => Err(GemError::Config(format!(
+                "Unknown STATE_STORE '{}', expected file or sqlite",
+                other
+            ))),
+        }
+    }
+}
+
+/// Opens the store picked with `STATE_STORE`, `file` (the default) or `sqlite`.
+pub fn open_store() -> GemResult<Arc<dyn StateStore>> {
+    let dir = notification_write_dir()?;
+
+    match StoreKind::from_env()? {
+        StoreKind::File => Ok(Arc::new(file::FileStore::new(dir))),
+        StoreKind::Sqlite => {
+            let path = env_var(STATE_DB_KEY)?.unwrap_or(format!("{}gem.sqlite", dir));
+            Ok(Arc::new(sqlite::SqliteStore::open(&path)?))
+        }
+    }
+}
+
+fn channel_key(channel: &NotificationType) -> String {
+    channel.to_string().to_lowercase()
+}
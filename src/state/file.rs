@@ -0,0 +1,248 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{
+    channel_key, HeldNotifications, PageCache, RunResult, SendHistory, StateStore, MAX_RUNS,
+};
+use crate::changes::Fingerprint;
+use crate::config::NotificationType;
+use crate::error::{GemError, GemResult};
+
+/// Keeps state as small files in `NOTIFICATION_WRITE_DIR`, one set per target.
+pub struct FileStore {
+    dir: String,
+}
+
+impl FileStore {
+    pub fn new(dir: String) -> FileStore {
+        FileStore { dir }
+    }
+
+    fn path(&self, kind: &str, target_id: &str, extension: &str) -> String {
+        format!("{}{}-{}{}", self.dir, kind, target_id, extension)
+    }
+}
+
+impl StateStore for FileStore {
+    fn send_history(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+    ) -> GemResult<Option<SendHistory>> {
+        let filename = self.path(
+            "last_sent",
+            &format!("{}-{}", target_id, channel_key(channel)),
+            "",
+        );
+        let Some(contents) = read_file(&filename)? else {
+            return Ok(None);
+        };
+
+        let parsed = contents.split_once('|').and_then(|(timestamp, tokens)| {
+            let last_sent = timestamp
+                .parse::<i64>()
+                .ok()
+                .and_then(DateTime::from_timestamp_millis)?;
+            let tokens = tokens.parse::<f64>().ok()?;
+            Some(SendHistory { last_sent, tokens })
+        });
+
+        if parsed.is_none() {
+            // it's replaced on the next send
+            eprintln!("Ignoring {}, unexpected format {}", filename, contents);
+        }
+        Ok(parsed)
+    }
+
+    fn save_send_history(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+        history: &SendHistory,
+    ) -> GemResult<()> {
+        let filename = self.path(
+            "last_sent",
+            &format!("{}-{}", target_id, channel_key(channel)),
+            "",
+        );
+        write_file(
+            &filename,
+            &format!(
+                "{}|{}",
+                history.last_sent.timestamp_millis(),
+                history.tokens
+            ),
+        )
+    }
+
+    fn fingerprints(&self, target_id: &str) -> GemResult<Vec<Fingerprint>> {
+        read_json(&self.path("matches", target_id, ".json"))
+    }
+
+    fn save_fingerprints(&self, target_id: &str, fingerprints: &[Fingerprint]) -> GemResult<()> {
+        write_json(&self.path("matches", target_id, ".json"), fingerprints)
+    }
+
+    fn held(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+    ) -> GemResult<Option<HeldNotifications>> {
+        read_value(&self.path(
+            "held",
+            &format!("{}-{}", target_id, channel_key(channel)),
+            ".json",
+        ))
+    }
+
+    fn save_held(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+        held: Option<&HeldNotifications>,
+    ) -> GemResult<()> {
+        let filename = self.path(
+            "held",
+            &format!("{}-{}", target_id, channel_key(channel)),
+            ".json",
+        );
+        match held {
+            Some(held) => write_value(&filename, held),
+            None => remove_file(&filename),
+        }
+    }
+
+    fn triggered_since(&self, target_id: &str) -> GemResult<Option<DateTime<Utc>>> {
+        let filename = self.path("triggered", target_id, "");
+        let Some(contents) = read_file(&filename)? else {
+            return Ok(None);
+        };
+
+        let parsed = contents
+            .parse::<i64>()
+            .ok()
+            .and_then(DateTime::from_timestamp_millis);
+        if parsed.is_none() {
+            // it's replaced on the next change
+            eprintln!("Ignoring {}, unexpected format {}", filename, contents);
+        }
+        Ok(parsed)
+    }
+
+    fn save_triggered_since(&self, target_id: &str, since: Option<DateTime<Utc>>) -> GemResult<()> {
+        let filename = self.path("triggered", target_id, "");
+        match since {
+            Some(since) => write_file(&filename, &since.timestamp_millis().to_string()),
+            None => remove_file(&filename),
+        }
+    }
+
+    fn page_cache(&self, target_id: &str) -> GemResult<Option<PageCache>> {
+        read_value(&self.path("page", target_id, ".json"))
+    }
+
+    fn save_page_cache(&self, target_id: &str, page_cache: &PageCache) -> GemResult<()> {
+        write_value(&self.path("page", target_id, ".json"), page_cache)
+    }
+
+    fn session(&self, target_id: &str) -> GemResult<Option<String>> {
+        read_file(&self.path("session", target_id, ".json"))
+    }
+
+    fn save_session(&self, target_id: &str, cookies: &str) -> GemResult<()> {
+        write_private_file(&self.path("session", target_id, ".json"), cookies)
+    }
+
+    fn recent_runs(&self, target_id: &str, limit: usize) -> GemResult<Vec<RunResult>> {
+        let runs = read_json::<RunResult>(&self.path("runs", target_id, ".json"))?;
+        Ok(runs.into_iter().rev().take(limit).collect())
+    }
+
+    fn record_run(&self, target_id: &str, run: &RunResult) -> GemResult<()> {
+        let filename = self.path("runs", target_id, ".json");
+        let mut runs = read_json::<RunResult>(&filename)?;
+        runs.push(run.clone());
+        if runs.len() > MAX_RUNS {
+            runs.drain(..runs.len() - MAX_RUNS);
+        }
+        write_json(&filename, &runs)
+    }
+}
+
+fn read_file(filename: &str) -> GemResult<Option<String>> {
+    if !Path::new(filename).exists() {
+        return Ok(None);
+    }
+
+    fs::read_to_string(filename)
+        .map(Some)
+        .map_err(|e| GemError::State(format!("Unable to read {}: {}", filename, e)))
+}
+
+/// Writes next to the file then renames it over the top so a crash can't leave half a file.
+fn write_file(filename: &str, contents: &str) -> GemResult<()> {
+    let temp_filename = format!("{}.tmp", filename);
+    fs::write(&temp_filename, contents)
+        .and_then(|_| fs::rename(&temp_filename, filename))
+        .map_err(|e| GemError::State(format!("Unable to write {}: {}", filename, e)))
+}
+
+/// Like `write_file` but only the owner can read it, for credentials such as session cookies.
+fn write_private_file(filename: &str, contents: &str) -> GemResult<()> {
+    let temp_filename = format!("{}.tmp", filename);
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_filename)
+        .and_then(|mut file| {
+            // the mode only applies when the file is new, not to one left by a crash
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+            file.write_all(contents.as_bytes())
+        })
+        .and_then(|_| fs::rename(&temp_filename, filename))
+        .map_err(|e| GemError::State(format!("Unable to write {}: {}", filename, e)))
+}
+
+fn remove_file(filename: &str) -> GemResult<()> {
+    if !Path::new(filename).exists() {
+        return Ok(());
+    }
+
+    fs::remove_file(filename)
+        .map_err(|e| GemError::State(format!("Unable to remove {}: {}", filename, e)))
+}
+
+fn read_json<T: DeserializeOwned>(filename: &str) -> GemResult<Vec<T>> {
+    Ok(read_value(filename)?.unwrap_or_default())
+}
+
+fn write_json<T: Serialize>(filename: &str, values: &[T]) -> GemResult<()> {
+    write_value(filename, values)
+}
+
+fn read_value<T: DeserializeOwned>(filename: &str) -> GemResult<Option<T>> {
+    let Some(contents) = read_file(filename)? else {
+        return Ok(None);
+    };
+
+    Ok(serde_json::from_str(&contents)
+        .map_err(|e| {
+            // it's replaced on the next save
+            eprintln!("Ignoring {}, unable to parse it: {}", filename, e);
+        })
+        .ok())
+}
+
+fn write_value<T: Serialize + ?Sized>(filename: &str, value: &T) -> GemResult<()> {
+    let contents = serde_json::to_string_pretty(value)
+        .map_err(|e| GemError::State(format!("Unable to serialise {}: {}", filename, e)))?;
+    write_file(filename, &contents)
+}
@@ -0,0 +1,382 @@
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    channel_key, HeldNotifications, PageCache, RunResult, SendHistory, StateStore, MAX_RUNS,
+};
+use crate::changes::Fingerprint;
+use crate::config::NotificationType;
+use crate::error::{GemError, GemResult};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS send_history (
+        target_id TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        last_sent TEXT NOT NULL,
+        tokens REAL NOT NULL,
+        PRIMARY KEY (target_id, channel)
+    );
+    CREATE TABLE IF NOT EXISTS fingerprints (
+        target_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        fingerprint TEXT NOT NULL,
+        PRIMARY KEY (target_id, position)
+    );
+    CREATE TABLE IF NOT EXISTS held (
+        target_id TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        notifications TEXT NOT NULL,
+        PRIMARY KEY (target_id, channel)
+    );
+    CREATE TABLE IF NOT EXISTS triggered (
+        target_id TEXT PRIMARY KEY,
+        since TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS page_cache (
+        target_id TEXT PRIMARY KEY,
+        etag TEXT,
+        last_modified TEXT,
+        hash TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        target_id TEXT PRIMARY KEY,
+        cookies TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        target_id TEXT NOT NULL,
+        at TEXT NOT NULL,
+        match_count INTEGER NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX IF NOT EXISTS runs_target_id ON runs (target_id, id);
+";
+
+/// Keeps state for every target in one SQLite database, set with `STATE_DB`.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens the database, creating it if need be. It holds saved sessions so only the owner
+    /// may read it, an existing one is narrowed to the owner too.
+    pub fn open(path: &str) -> GemResult<SqliteStore> {
+        // SQLite gives its journal files the same mode as the database
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(path)
+            .and_then(|file| file.set_permissions(fs::Permissions::from_mode(0o600)))
+            .map_err(|e| GemError::State(format!("Unable to create {}: {}", path, e)))?;
+
+        let connection = Connection::open(path)
+            .map_err(|e| GemError::State(format!("Unable to open {}: {}", path, e)))?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|e| GemError::State(format!("Unable to set up {}: {}", path, e)))?;
+
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        // a panic mid-query leaves nothing half done that SQLite won't have rolled back
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn state_error(e: rusqlite::Error) -> GemError {
+    GemError::State(format!("SQLite error: {}", e))
+}
+
+impl StateStore for SqliteStore {
+    fn send_history(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+    ) -> GemResult<Option<SendHistory>> {
+        self.connection()
+            .query_row(
+                "SELECT last_sent, tokens FROM send_history WHERE target_id = ?1 AND channel = ?2",
+                params![target_id, channel_key(channel)],
+                |row| {
+                    Ok(SendHistory {
+                        last_sent: row.get(0)?,
+                        tokens: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(state_error)
+    }
+
+    fn save_send_history(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+        history: &SendHistory,
+    ) -> GemResult<()> {
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO send_history (target_id, channel, last_sent, tokens)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    target_id,
+                    channel_key(channel),
+                    history.last_sent,
+                    history.tokens
+                ],
+            )
+            .map(|_| ())
+            .map_err(state_error)
+    }
+
+    fn fingerprints(&self, target_id: &str) -> GemResult<Vec<Fingerprint>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT fingerprint FROM fingerprints WHERE target_id = ?1 ORDER BY position")
+            .map_err(state_error)?;
+        let rows = statement
+            .query_map(params![target_id], |row| row.get::<_, String>(0))
+            .map_err(state_error)?;
+
+        rows.map(|row| {
+            let fingerprint = row.map_err(state_error)?;
+            serde_json::from_str(&fingerprint)
+                .map_err(|e| GemError::State(format!("Unable to parse saved match: {}", e)))
+        })
+        .collect()
+    }
+
+    fn save_fingerprints(&self, target_id: &str, fingerprints: &[Fingerprint]) -> GemResult<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(state_error)?;
+
+        transaction
+            .execute(
+                "DELETE FROM fingerprints WHERE target_id = ?1",
+                params![target_id],
+            )
+            .map_err(state_error)?;
+        for (position, fingerprint) in fingerprints.iter().enumerate() {
+            let fingerprint = serde_json::to_string(fingerprint)
+                .map_err(|e| GemError::State(format!("Unable to serialise match: {}", e)))?;
+            transaction
+                .execute(
+                    "INSERT INTO fingerprints (target_id, position, fingerprint) VALUES (?1, ?2, ?3)",
+                    params![target_id, position, fingerprint],
+                )
+                .map_err(state_error)?;
+        }
+
+        transaction.commit().map_err(state_error)
+    }
+
+    fn held(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+    ) -> GemResult<Option<HeldNotifications>> {
+        let notifications = self
+            .connection()
+            .query_row(
+                "SELECT notifications FROM held WHERE target_id = ?1 AND channel = ?2",
+                params![target_id, channel_key(channel)],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(state_error)?;
+
+        notifications
+            .map(|notifications| {
+                serde_json::from_str(&notifications).map_err(|e| {
+                    GemError::State(format!("Unable to parse held notifications: {}", e))
+                })
+            })
+            .transpose()
+    }
+
+    fn save_held(
+        &self,
+        target_id: &str,
+        channel: &NotificationType,
+        held: Option<&HeldNotifications>,
+    ) -> GemResult<()> {
+        let Some(held) = held else {
+            return self
+                .connection()
+                .execute(
+                    "DELETE FROM held WHERE target_id = ?1 AND channel = ?2",
+                    params![target_id, channel_key(channel)],
+                )
+                .map(|_| ())
+                .map_err(state_error);
+        };
+
+        let notifications = serde_json::to_string(held).map_err(|e| {
+            GemError::State(format!("Unable to serialise held notifications: {}", e))
+        })?;
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO held (target_id, channel, notifications) VALUES (?1, ?2, ?3)",
+                params![target_id, channel_key(channel), notifications],
+            )
+            .map(|_| ())
+            .map_err(state_error)
+    }
+
+    fn triggered_since(&self, target_id: &str) -> GemResult<Option<DateTime<Utc>>> {
+        self.connection()
+            .query_row(
+                "SELECT since FROM triggered WHERE target_id = ?1",
+                params![target_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(state_error)
+    }
+
+    fn save_triggered_since(&self, target_id: &str, since: Option<DateTime<Utc>>) -> GemResult<()> {
+        let connection = self.connection();
+        let result = match since {
+            Some(since) => connection.execute(
+                "INSERT OR REPLACE INTO triggered (target_id, since) VALUES (?1, ?2)",
+                params![target_id, since],
+            ),
+            None => connection.execute(
+                "DELETE FROM triggered WHERE target_id = ?1",
+                params![target_id],
+            ),
+        };
+        result.map(|_| ()).map_err(state_error)
+    }
+
+    fn page_cache(&self, target_id: &str) -> GemResult<Option<PageCache>> {
+        self.connection()
+            .query_row(
+                "SELECT etag, last_modified, hash FROM page_cache WHERE target_id = ?1",
+                params![target_id],
+                |row| {
+                    Ok(PageCache {
+                        etag: row.get(0)?,
+                        last_modified: row.get(1)?,
+                        hash: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(state_error)
+    }
+
+    fn save_page_cache(&self, target_id: &str, page_cache: &PageCache) -> GemResult<()> {
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO page_cache (target_id, etag, last_modified, hash)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    target_id,
+                    page_cache.etag,
+                    page_cache.last_modified,
+                    page_cache.hash
+                ],
+            )
+            .map(|_| ())
+            .map_err(state_error)
+    }
+
+    fn session(&self, target_id: &str) -> GemResult<Option<String>> {
+        self.connection()
+            .query_row(
+                "SELECT cookies FROM sessions WHERE target_id = ?1",
+                params![target_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(state_error)
+    }
+
+    fn save_session(&self, target_id: &str, cookies: &str) -> GemResult<()> {
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO sessions (target_id, cookies) VALUES (?1, ?2)",
+                params![target_id, cookies],
+            )
+            .map(|_| ())
+            .map_err(state_error)
+    }
+
+    fn recent_runs(&self, target_id: &str, limit: usize) -> GemResult<Vec<RunResult>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare(
+                "SELECT at, match_count, error FROM runs WHERE target_id = ?1
+                 ORDER BY id DESC LIMIT ?2",
+            )
+            .map_err(state_error)?;
+        let rows = statement
+            .query_map(params![target_id, limit], |row| {
+                Ok(RunResult {
+                    at: row.get(0)?,
+                    match_count: row.get(1)?,
+                    error: row.get(2)?,
+                })
+            })
+            .map_err(state_error)?;
+
+        rows.map(|row| row.map_err(state_error)).collect()
+    }
+
+    fn record_run(&self, target_id: &str, run: &RunResult) -> GemResult<()> {
+        let connection = self.connection();
+        connection
+            .execute(
+                "INSERT INTO runs (target_id, at, match_count, error) VALUES (?1, ?2, ?3, ?4)",
+                params![target_id, run.at, run.match_count, run.error],
+            )
+            .map_err(state_error)?;
+        connection
+            .execute(
+                "DELETE FROM runs WHERE target_id = ?1 AND id NOT IN
+                 (SELECT id FROM runs WHERE target_id = ?1 ORDER BY id DESC LIMIT ?2)",
+                params![target_id, MAX_RUNS],
+            )
+            .map(|_| ())
+            .map_err(state_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(path: &str) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn only_the_owner_can_read_the_database() {
+        let dir = std::env::temp_dir().join(format!("gem-sqlite-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let new_path = dir.join("new.db").to_string_lossy().to_string();
+        SqliteStore::open(&new_path).unwrap();
+        assert_eq!(mode(&new_path), 0o600);
+
+        let old_path = dir.join("old.db").to_string_lossy().to_string();
+        fs::write(&old_path, "").unwrap();
+        fs::set_permissions(&old_path, fs::Permissions::from_mode(0o644)).unwrap();
+        SqliteStore::open(&old_path).unwrap();
+        assert_eq!(mode(&old_path), 0o600);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}